@@ -39,6 +39,8 @@ fn main() {
             mode: WindowMode::Windowed,
             ..default()
         },
+        light_budget: default(),
+        light_margin: 16,
     });
     app.add_plugins(AnimPlugin::new());
     app.add_plugins(Light64Plugin::default());
@@ -129,6 +131,7 @@ fn startup(mut commands: Commands, ass: Res<AssetServer>) {
         Dyno::new(0.0, 0.0),
         StaticRx::single(StaticRxKind::Default, player_hbox.clone()),
         TriggerRx::single(TriggerRxKind::Player, player_hbox.clone()),
+        ShadowCaster::single(player_hbox.clone()),
         MainStaticLayer::RENDER_LAYERS,
         LightMan::new(Light64Anim::On),
     ));
@@ -146,6 +149,27 @@ fn startup(mut commands: Commands, ass: Res<AssetServer>) {
         UVec2::new(SCREEN_UVEC.x / 2, 12),
     ));
 
+    commands.spawn((
+        Name::new("Pillar"),
+        Pos::new(-SCREEN_VEC.x / 4.0, -SCREEN_VEC.y / 2.0 + 36.0),
+        Sprite {
+            custom_size: Some(Vec2::new(8.0, 48.0)),
+            color: Color::linear_rgb(0.6, 0.6, 0.6),
+            ..default()
+        },
+        ShadowCaster::single(HBox::new(8, 48)),
+        MainDetailLayer::RENDER_LAYERS,
+    ));
+
+    commands.spawn((
+        Name::new("Torch"),
+        Pos::new(SCREEN_VEC.x / 4.0, -SCREEN_VEC.y / 4.0),
+        ProcLight::radial(48.0)
+            .with_color(Color::linear_rgb(1.0, 0.6, 0.2))
+            .with_falloff(2.0),
+        LightShadows::soft(6.0).with_penetration(3.0),
+    ));
+
     commands.spawn((
         SpikeBundle::new(Pos::new(-SCREEN_VEC.x / 2.0, 18.0), UVec2::new(36, 24)),
         MainAmbienceLayer::RENDER_LAYERS,
@@ -231,7 +255,7 @@ fn camera_follow_player(
 ) {
     let mut cam_pos = camera_q.single_mut();
     let player_pos = player_q.single();
-    *cam_pos = player_pos.clone();
+    *cam_pos = *player_pos;
 }
 
 fn toggle_light(
@@ -2,7 +2,7 @@ use bevy::prelude::*;
 use camera_shake::CameraShake;
 
 use crate::{
-    layer::{layer_defns::SmushLayer, resize_layers_as_needed, Layer, LayerInternal},
+    layer::{layer_defns::SmushLayer, resize_layers_as_needed, Layer, LayerDefn},
     plugin::LayersRes,
     LayersCameraSet,
 };
@@ -13,6 +13,7 @@ pub(crate) mod camera_shake;
 /// Invariants:
 /// - Either 0 or 1 of these must exist at all time
 /// - It must have a Pos
+///
 /// The actual camera pos is the IPos of this entity. That means it's only updated during PhysicsSet. Be warned.
 #[derive(Component)]
 #[require(bevy_2delight_physics::prelude::Pos)]
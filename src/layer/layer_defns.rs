@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use super::{Layer, LayerInternal, LayerOutputMode, LayerPositionMode};
+use super::{Layer, LayerDefn, LayerOutputMode, LayerPositionMode};
 
 const LIGHT_KEY: u32 = 1;
 const BG_KEY: u32 = 2;
@@ -14,20 +14,20 @@ const TRANSITION_KEY: u32 = 9;
 const SMUSH_KEY: u32 = 10;
 
 /// This is when all the light sources must render + cutout
-pub(crate) const PRE_LIGHT_RENDER_ORDER: u32 = 1;
+pub const PRE_LIGHT_RENDER_ORDER: u32 = 1;
 /// This is when light renders
-pub(crate) const LIGHT_RENDER_ORDER: u32 = 2;
+pub const LIGHT_RENDER_ORDER: u32 = 2;
 /// This is everything that depends on light
-pub(crate) const POST_LIGHT_RENDER_ORDER: u32 = 3;
+pub const POST_LIGHT_RENDER_ORDER: u32 = 3;
 /// This is stuff that is smushing together multiple layers, happens last
-pub(crate) const SMUSH_RENDER_ORDER: u32 = 4;
+pub const SMUSH_RENDER_ORDER: u32 = 4;
 
 #[derive(Debug, Default)]
 pub struct LightLayer;
 impl Layer for LightLayer {
-    const _KEY: u32 = LIGHT_KEY;
+    const KEY: u32 = LIGHT_KEY;
 }
-impl LayerInternal for LightLayer {
+impl LayerDefn for LightLayer {
     const RENDER_ORDER: u32 = LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = false;
     const ZIX: u32 = 0;
@@ -38,9 +38,9 @@ impl LayerInternal for LightLayer {
 #[derive(Debug, Default)]
 pub struct BgLayer;
 impl Layer for BgLayer {
-    const _KEY: u32 = BG_KEY;
+    const KEY: u32 = BG_KEY;
 }
-impl LayerInternal for BgLayer {
+impl LayerDefn for BgLayer {
     const RENDER_ORDER: u32 = POST_LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = false;
     const ZIX: u32 = LightLayer::ZIX + 1;
@@ -54,9 +54,9 @@ impl LayerInternal for BgLayer {
 #[derive(Debug, Default)]
 pub struct MainAmbienceLayer;
 impl Layer for MainAmbienceLayer {
-    const _KEY: u32 = MAIN_AMBIENCE_KEY;
+    const KEY: u32 = MAIN_AMBIENCE_KEY;
 }
-impl LayerInternal for MainAmbienceLayer {
+impl LayerDefn for MainAmbienceLayer {
     const RENDER_ORDER: u32 = POST_LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = false;
     const ZIX: u32 = BgLayer::ZIX + 1;
@@ -70,9 +70,9 @@ impl LayerInternal for MainAmbienceLayer {
 #[derive(Debug, Default)]
 pub struct MainDetailLayer;
 impl Layer for MainDetailLayer {
-    const _KEY: u32 = MAIN_DETAIL_KEY;
+    const KEY: u32 = MAIN_DETAIL_KEY;
 }
-impl LayerInternal for MainDetailLayer {
+impl LayerDefn for MainDetailLayer {
     const RENDER_ORDER: u32 = POST_LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = false;
     const ZIX: u32 = MainAmbienceLayer::ZIX + 1;
@@ -86,9 +86,9 @@ impl LayerInternal for MainDetailLayer {
 #[derive(Debug, Default)]
 pub struct MainStaticLayer;
 impl Layer for MainStaticLayer {
-    const _KEY: u32 = MAIN_STATIC_LAYER;
+    const KEY: u32 = MAIN_STATIC_LAYER;
 }
-impl LayerInternal for MainStaticLayer {
+impl LayerDefn for MainStaticLayer {
     const RENDER_ORDER: u32 = POST_LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = false;
     const ZIX: u32 = MainDetailLayer::ZIX + 1;
@@ -101,9 +101,9 @@ impl LayerInternal for MainStaticLayer {
 #[derive(Debug, Default)]
 pub struct FgLayer;
 impl Layer for FgLayer {
-    const _KEY: u32 = FG_KEY;
+    const KEY: u32 = FG_KEY;
 }
-impl LayerInternal for FgLayer {
+impl LayerDefn for FgLayer {
     const RENDER_ORDER: u32 = POST_LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = false;
     const ZIX: u32 = MainStaticLayer::ZIX + 1;
@@ -116,9 +116,9 @@ impl LayerInternal for FgLayer {
 #[derive(Debug, Default)]
 pub struct OverlayLayer;
 impl Layer for OverlayLayer {
-    const _KEY: u32 = OVERLAY_KEY;
+    const KEY: u32 = OVERLAY_KEY;
 }
-impl LayerInternal for OverlayLayer {
+impl LayerDefn for OverlayLayer {
     const RENDER_ORDER: u32 = POST_LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = true;
     const ZIX: u32 = FgLayer::ZIX + 1;
@@ -131,9 +131,9 @@ impl LayerInternal for OverlayLayer {
 #[derive(Debug, Default)]
 pub struct MenuLayer;
 impl Layer for MenuLayer {
-    const _KEY: u32 = MENU_KEY;
+    const KEY: u32 = MENU_KEY;
 }
-impl LayerInternal for MenuLayer {
+impl LayerDefn for MenuLayer {
     const RENDER_ORDER: u32 = POST_LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = true;
     const ZIX: u32 = OverlayLayer::ZIX + 1;
@@ -146,9 +146,9 @@ impl LayerInternal for MenuLayer {
 #[derive(Debug, Default)]
 pub struct TransitionLayer;
 impl Layer for TransitionLayer {
-    const _KEY: u32 = TRANSITION_KEY;
+    const KEY: u32 = TRANSITION_KEY;
 }
-impl LayerInternal for TransitionLayer {
+impl LayerDefn for TransitionLayer {
     const RENDER_ORDER: u32 = POST_LIGHT_RENDER_ORDER;
     const IS_OVERLAY: bool = true;
     const ZIX: u32 = MenuLayer::ZIX + 1;
@@ -161,9 +161,9 @@ impl LayerInternal for TransitionLayer {
 #[derive(Debug, Default)]
 pub struct SmushLayer;
 impl Layer for SmushLayer {
-    const _KEY: u32 = SMUSH_KEY;
+    const KEY: u32 = SMUSH_KEY;
 }
-impl LayerInternal for SmushLayer {
+impl LayerDefn for SmushLayer {
     const RENDER_ORDER: u32 = SMUSH_RENDER_ORDER;
     const IS_OVERLAY: bool = true;
     const ZIX: u32 = TransitionLayer::ZIX + 1;
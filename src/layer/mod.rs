@@ -3,32 +3,34 @@ use bevy::{
     render::{camera::RenderTarget, view::RenderLayers},
     window::WindowResized,
 };
-use layer_defns::{
-    BgLayer, FgLayer, LightLayer, MainAmbienceLayer, MainDetailLayer, MainStaticLayer, MenuLayer,
-    OverlayLayer, SmushLayer, TransitionLayer,
-};
+use layer_defns::{LightLayer, SmushLayer};
 
 use crate::{
     camera::FollowDynamicCamera,
     consts::{COLOR_NONE, ZIX_MAX, ZIX_MIN},
-    light::light_mat::LightApplyMat,
-    plugin::LayersRes,
-    utils::blank_screen_image,
+    light::{
+        light_ambience::{LayerAmbience, LitLayerOutput},
+        light_mat::LightApplyMat,
+    },
+    plugin::{init_root_eid, LayersRes},
+    utils::{blank_image, blank_screen_image},
+    DUMMY_LAYER_USIZE,
 };
 
 pub(crate) mod layer_defns;
 
 /// Key information defining a layer
-pub trait Layer: std::fmt::Debug + Default {
-    /// A unique key to be given to this layer
-    #[doc(hidden)]
-    const _KEY: u32;
+pub trait Layer: std::fmt::Debug + Default + Send + Sync + 'static {
+    /// A unique key to be given to this layer. Doubles as the render layer of the layer.
+    /// NOTE: Keys 1-10 are used by the built-in layers, and lights claim render layers starting at 100,
+    ///       so custom layers should pick something in 11-99. Clashes panic at startup.
+    const KEY: u32;
     /// Which render layers to attach to things in this layer
-    const RENDER_LAYERS: RenderLayers = RenderLayers::layer(Self::_KEY as usize);
+    const RENDER_LAYERS: RenderLayers = RenderLayers::layer(Self::KEY as usize);
 }
 
 /// How this layer should react to the camera moving
-pub(crate) enum LayerPositionMode {
+pub enum LayerPositionMode {
     /// The camera's position will always be the center of the screen
     /// NOTE: Used for the actual world with game objects
     Follow,
@@ -38,7 +40,7 @@ pub(crate) enum LayerPositionMode {
 }
 
 /// What shader should be applied to the output of this layer
-pub(crate) enum LayerOutputMode {
+pub enum LayerOutputMode {
     /// Just render to target
     None,
     /// Render to target, then put that target on a sprite in the output RenderLayers
@@ -54,10 +56,11 @@ pub(crate) enum LayerOutputMode {
 #[derive(Component)]
 pub(crate) struct LayerNeedsResizing;
 
-/// Key information about a layer only to be used in this crate
-pub(crate) trait LayerInternal: Layer {
+/// Everything needed to actually set up a layer. Implement this (plus `Layer`) and add a
+/// `LayerDefnPlugin` to get a custom layer.
+pub trait LayerDefn: Layer {
     /// A handle to the underlying
-    const TARGET: Handle<Image> = Handle::weak_from_u128(Self::_KEY as u128);
+    const TARGET: Handle<Image> = Handle::weak_from_u128(Self::KEY as u128);
     /// In what order should this layer get rendered?
     /// EXAMPLE: Important for things like making sure that certain layers rendered AFTER lighting
     const RENDER_ORDER: u32;
@@ -72,86 +75,92 @@ pub(crate) trait LayerInternal: Layer {
     const LAYER_OUTPUT_MODE: LayerOutputMode;
     /// Potentially a custom clear color
     const CLEAR_COLOR: Color = COLOR_NONE;
+}
 
-    fn setup(
-        commands: &mut Commands,
-        res: &LayersRes,
-        images: &mut ResMut<Assets<Image>>,
-        meshes: &mut ResMut<Assets<Mesh>>,
-        light_apply_mats: &mut ResMut<Assets<LightApplyMat>>,
-    ) {
-        let is_smush = Self::_KEY == SmushLayer::_KEY;
-        // Render to a target
-        let render_target = if is_smush {
-            RenderTarget::default()
-        } else {
-            let image = blank_screen_image(res, Self::IS_OVERLAY);
-            images.insert(Self::TARGET.id(), image);
-            RenderTarget::Image(Self::TARGET)
-        };
-        let mut comms = commands.spawn((
-            Name::new(format!("LayerTargetCamera_{:?}", Self::default())),
-            Camera2d,
-            Camera {
-                order: Self::RENDER_ORDER as isize,
-                target: render_target,
-                clear_color: ClearColorConfig::Custom(Self::CLEAR_COLOR),
-                ..default()
+fn setup_layer<L: LayerDefn>(
+    mut commands: Commands,
+    res: Res<LayersRes>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut light_apply_mats: ResMut<Assets<LightApplyMat>>,
+) {
+    let is_smush = L::KEY == SmushLayer::KEY;
+    // Render to a target
+    let render_target = if is_smush {
+        RenderTarget::default()
+    } else if L::KEY == LightLayer::KEY {
+        // Lighting is drawn past the edges of the screen, so shakes and pans have light to show
+        images.insert(L::TARGET.id(), blank_image(res.light_target_size()));
+        RenderTarget::Image(L::TARGET)
+    } else {
+        let image = blank_screen_image(&res, L::IS_OVERLAY);
+        images.insert(L::TARGET.id(), image);
+        RenderTarget::Image(L::TARGET)
+    };
+    let mut comms = commands.spawn((
+        Name::new(format!("LayerTargetCamera_{:?}", L::default())),
+        Camera2d,
+        Camera {
+            order: L::RENDER_ORDER as isize,
+            target: render_target,
+            clear_color: ClearColorConfig::Custom(L::CLEAR_COLOR),
+            ..default()
+        },
+        OrthographicProjection {
+            near: ZIX_MIN,
+            far: ZIX_MAX,
+            scale: if L::IS_OVERLAY && !is_smush {
+                1.0 / res.overlay_growth as f32
+            } else {
+                1.0
             },
-            OrthographicProjection {
-                near: ZIX_MIN,
-                far: ZIX_MAX,
-                scale: if Self::IS_OVERLAY && !is_smush {
-                    1.0 / res.overlay_growth as f32
-                } else {
-                    1.0
+            ..OrthographicProjection::default_2d()
+        },
+        L::RENDER_LAYERS,
+    ));
+    comms.set_parent(res.root_eid());
+    if matches!(L::LAYER_POSITION_MODE, LayerPositionMode::Follow) {
+        comms.insert(FollowDynamicCamera);
+    }
+
+    // Maybe do other stuff
+    match L::LAYER_OUTPUT_MODE {
+        LayerOutputMode::None => (),
+        LayerOutputMode::Unlit { output_rl } => {
+            // TODO: Handle lit material differently
+            commands.spawn((
+                Name::new(format!("LayerUnlitOutput_{:?}", L::default())),
+                output_rl,
+                Transform::from_translation(Vec3::Z * L::ZIX as f32),
+                Visibility::default(),
+                Sprite {
+                    image: L::TARGET,
+                    custom_size: Some((res.screen_size * res.overlay_growth).as_vec2()),
+                    ..default()
                 },
-                ..OrthographicProjection::default_2d()
-            },
-            Self::RENDER_LAYERS,
-        ));
-        comms.set_parent(res.root_eid());
-        if matches!(Self::LAYER_POSITION_MODE, LayerPositionMode::Follow) {
-            comms.insert(FollowDynamicCamera);
+                LayerNeedsResizing,
+            ));
         }
-
-        // Maybe do other stuff
-        match Self::LAYER_OUTPUT_MODE {
-            LayerOutputMode::None => (),
-            LayerOutputMode::Unlit { output_rl } => {
-                // TODO: Handle lit material differently
-                commands.spawn((
-                    Name::new(format!("LayerUnlitOutput_{:?}", Self::default())),
-                    output_rl,
-                    Transform::from_translation(Vec3::Z * Self::ZIX as f32),
-                    Visibility::default(),
-                    Sprite {
-                        image: Self::TARGET,
-                        custom_size: Some((res.screen_size * res.overlay_growth).as_vec2()),
-                        ..default()
-                    },
-                    LayerNeedsResizing,
-                ));
-            }
-            LayerOutputMode::Lit {
+        LayerOutputMode::Lit {
+            output_rl,
+            base_color,
+        } => {
+            let custom_size = (res.screen_size * res.overlay_growth).as_vec2();
+            let mesh = Mesh::from(Rectangle::new(custom_size.x, custom_size.y));
+            let mesh_hand = meshes.add(mesh);
+            let light_scale = res.screen_size.as_vec2() / res.light_target_size().as_vec2();
+            let mat = LightApplyMat::new(L::TARGET, LightLayer::TARGET, base_color, light_scale);
+            let mat_hand = light_apply_mats.add(mat);
+            commands.spawn((
+                Name::new(format!("LayerLitOutput_{:?}", L::default())),
                 output_rl,
-                base_color,
-            } => {
-                let custom_size = (res.screen_size * res.overlay_growth).as_vec2();
-                let mesh = Mesh::from(Rectangle::new(custom_size.x, custom_size.y));
-                let mesh_hand = meshes.add(mesh);
-                let mat = LightApplyMat::new(Self::TARGET, LightLayer::TARGET, base_color);
-                let mat_hand = light_apply_mats.add(mat);
-                commands.spawn((
-                    Name::new(format!("LayerLitOutput_{:?}", Self::default())),
-                    output_rl,
-                    Transform::from_translation(Vec3::Z * Self::ZIX as f32),
-                    Visibility::default(),
-                    Mesh2d(mesh_hand),
-                    MeshMaterial2d(mat_hand),
-                    LayerNeedsResizing,
-                ));
-            }
+                Transform::from_translation(Vec3::Z * L::ZIX as f32),
+                Visibility::default(),
+                Mesh2d(mesh_hand),
+                MeshMaterial2d(mat_hand),
+                LayerNeedsResizing,
+                LitLayerOutput { key: L::KEY },
+            ));
         }
     }
 }
@@ -179,36 +188,83 @@ pub(crate) fn resize_layers_as_needed(
     }
 }
 
-pub(crate) fn setup_all_layers(
-    mut commands: Commands,
-    layers_res: Res<LayersRes>,
-    mut images: ResMut<Assets<Image>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut light_apply_mats: ResMut<Assets<LightApplyMat>>,
-) {
-    macro_rules! setup_layers_helper {
-        ($($layer:ty$(,)?)*) => {
-            $(
-                <$layer>::setup(
-                    &mut commands,
-                    &layers_res,
-                    &mut images,
-                    &mut meshes,
-                    &mut light_apply_mats,
+/// Every layer registered so far, so clashing keys are caught before anything renders
+#[derive(Resource, Default)]
+pub(crate) struct RegisteredLayers {
+    /// (key, name) of each layer
+    keys: Vec<(u32, String)>,
+}
+impl RegisteredLayers {
+    fn register(&mut self, key: u32, name: String) {
+        if key as usize == DUMMY_LAYER_USIZE {
+            panic!("Layer {name} uses key {key}, which is reserved for the dummy render layer");
+        }
+        if let Some((_, other)) = self.keys.iter().find(|(other_key, _)| *other_key == key) {
+            panic!("Layers {other} and {name} both use key {key}, layer keys must be unique");
+        }
+        self.keys.push((key, name));
+    }
+    /// Panics if any layer's render layer falls in a range that is already spoken for
+    pub(crate) fn check_reserved(&self, reserved: std::ops::Range<usize>, reserved_for: &str) {
+        for (key, name) in self.keys.iter() {
+            if reserved.contains(&(*key as usize)) {
+                panic!(
+                    "Layer {name} uses key {key}, but render layers {}..{} are reserved for {reserved_for}",
+                    reserved.start, reserved.end
                 );
-            )*
-        };
+            }
+        }
+    }
+}
+
+/// Registers a layer so that it gets a camera, target, and output at startup.
+/// The built-in layers are all added by `LayersPlugin`, so this is only needed for custom layers.
+/// NOTE: Panics if the layer's key is already taken by another layer.
+#[derive(Default)]
+pub struct LayerDefnPlugin<L: LayerDefn> {
+    _pd: std::marker::PhantomData<L>,
+}
+impl<L: LayerDefn> Plugin for LayerDefnPlugin<L> {
+    fn build(&self, app: &mut App) {
+        app.world_mut()
+            .get_resource_or_insert_with(RegisteredLayers::default)
+            .register(L::KEY, format!("{:?}", L::default()));
+        // Seed the ambience now so anything set during `Startup` isn't clobbered by layer setup
+        if let LayerOutputMode::Lit { base_color, .. } = L::LAYER_OUTPUT_MODE {
+            app.world_mut()
+                .get_resource_or_insert_with(LayerAmbience::default)
+                .init(L::KEY, base_color);
+        }
+        app.add_systems(Startup, setup_layer::<L>.after(init_root_eid));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "both use key 11")]
+    fn duplicate_keys_panic() {
+        let mut registered = RegisteredLayers::default();
+        registered.register(11, "WaterLayer".into());
+        registered.register(11, "HudLayer".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "reserved for lights")]
+    fn keys_in_light_range_panic() {
+        let mut registered = RegisteredLayers::default();
+        registered.register(11, "WaterLayer".into());
+        registered.register(120, "HudLayer".into());
+        registered.check_reserved(100..356, "lights");
+    }
+
+    #[test]
+    fn distinct_keys_pass() {
+        let mut registered = RegisteredLayers::default();
+        registered.register(11, "WaterLayer".into());
+        registered.register(12, "HudLayer".into());
+        registered.check_reserved(100..356, "lights");
     }
-    setup_layers_helper!(
-        LightLayer,
-        BgLayer,
-        MainAmbienceLayer,
-        MainDetailLayer,
-        MainStaticLayer,
-        FgLayer,
-        OverlayLayer,
-        MenuLayer,
-        TransitionLayer,
-        SmushLayer
-    );
 }
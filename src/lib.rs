@@ -6,11 +6,22 @@ pub mod prelude {
     pub use super::layer::{
         layer_defns::{
             BgLayer, FgLayer, LightLayer, MainAmbienceLayer, MainDetailLayer, MainStaticLayer,
-            MenuLayer, OverlayLayer, TransitionLayer,
+            MenuLayer, OverlayLayer, SmushLayer, TransitionLayer, LIGHT_RENDER_ORDER,
+            POST_LIGHT_RENDER_ORDER, PRE_LIGHT_RENDER_ORDER, SMUSH_RENDER_ORDER,
         },
-        Layer,
+        Layer, LayerDefn, LayerDefnPlugin, LayerOutputMode, LayerPositionMode,
+    };
+    pub use super::light::light_alloc::{LightAllocFailed, LightBudget};
+    pub use super::light::light_alpha_caster::AlphaShadowCaster;
+    pub use super::light::light_ambience::LayerAmbience;
+    pub use super::light::light_caster::{
+        CasterShape, LightContainment, LightShadows, NoStaticTxShadow, ShadowCaster, ShadowSettings,
     };
     pub use super::light::light_man::{LightAnim, LightDefnPlugin, LightMan};
+    pub use super::light::light_probe::LightProbe;
+    pub use super::light::light_proc::{LightCone, LightFacing, LightFacingPlugin, ProcLight};
+    pub use super::light::light_sensor::{LightEntered, LightExited, LightSensor, LitBy};
+    pub use super::light::light_sun::SunLight;
     pub use super::parallax::{ParallaxX, ParallaxY};
     pub use super::plugin::LayersPlugin;
     pub use super::{LayersCameraSet, LightAnimSet, LightInteractionSet};
@@ -1,144 +1,693 @@
 use std::collections::VecDeque;
 
+use bevy::asset::RenderAssetUsages;
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::transform::TransformSystem;
+use bevy::utils::{HashMap, HashSet};
 
 pub const BASE_LIGHT_RENDER_LAYER: usize = 100;
 pub const MAX_NUM_LIGHTS: usize = 256;
+pub const LIGHT_ATLAS_SIZE: u32 = 2048;
+pub const MAX_NUM_SUNS: usize = 4;
 
 use bevy::render::camera::RenderTarget;
-use bevy::render::view::RenderLayers;
+use bevy::render::primitives::Aabb;
+use bevy::render::view::{NoFrustumCulling, RenderLayers, VisibilitySystems};
+use bevy_2delight_physics::prelude::Pos;
 
-use crate::camera::FollowDynamicCamera;
+use crate::camera::{camera_shake::CameraShake, DynamicCamera};
 use crate::consts::{COLOR_NONE, ZIX_MAX, ZIX_MIN};
 use crate::layer::layer_defns::{LightLayer, PRE_LIGHT_RENDER_ORDER};
 use crate::layer::Layer;
 use crate::plugin::LayersRes;
-use crate::utils::blank_screen_image;
-use crate::DUMMY_LAYER_USIZE;
+use crate::utils::{blank_image, color_as_vec4};
+use crate::{LayersCameraSet, LightInteractionSet, DUMMY_LAYER_USIZE};
 
+use super::light_interaction::SHADOW_MAT_HAND;
 use super::light_mat::LightCutoutMat;
+use super::light_source::LightSource;
 
-/// Facilitates assigning lights to different render layers so that they don't
-/// interfere with each other
-#[derive(Resource, Clone, Debug, Reflect)]
-pub(super) struct LightAllocer {
-    unused_render_layers: VecDeque<usize>,
+/// The image every point light (and its shadows) gets drawn into, each in a tile of its own
+const LIGHT_ATLAS_HAND: Handle<Image> = Handle::weak_from_u128(8712349817239487123);
+/// The single mesh that copies every light out of the atlas and into the `LightLayer`
+const LIGHT_AGG_MESH_HAND: Handle<Mesh> = Handle::weak_from_u128(2340982173409812734);
+
+/// How many lights can be drawn at once, and which render layers they get drawn on.
+/// Point lights are all drawn into one atlas on `base_render_layer`, and each `SunLight` gets a render layer
+/// of its own right after it, so make sure `render_layers()` doesn't overlap any layers you're using.
+#[derive(Clone, Copy, Debug, Reflect)]
+pub struct LightBudget {
+    pub base_render_layer: usize,
+    /// How many point lights can be drawn at once
+    pub capacity: usize,
+    /// The side length (in pixels) of the atlas point lights are drawn into. Rounded up to a power of two.
+    /// Each light takes a square tile, the smallest power of two that covers its radius, so a 2048 atlas
+    /// fits 16 lights of radius 256, or 1024 of radius 32.
+    pub atlas_size: u32,
+    /// How many `SunLight`s can be drawn at once
+    pub sun_capacity: usize,
 }
-impl Default for LightAllocer {
+impl LightBudget {
+    /// Every render layer the lights might use
+    pub fn render_layers(&self) -> std::ops::Range<usize> {
+        self.base_render_layer..(self.base_render_layer + 1 + self.sun_capacity)
+    }
+}
+impl Default for LightBudget {
     fn default() -> Self {
-        let mut unused_render_layers = VecDeque::new();
-        for rl in BASE_LIGHT_RENDER_LAYER..(BASE_LIGHT_RENDER_LAYER + MAX_NUM_LIGHTS) {
-            unused_render_layers.push_back(rl);
-        }
         Self {
-            unused_render_layers,
+            base_render_layer: BASE_LIGHT_RENDER_LAYER,
+            capacity: MAX_NUM_LIGHTS,
+            atlas_size: LIGHT_ATLAS_SIZE,
+            sun_capacity: MAX_NUM_SUNS,
         }
     }
 }
-impl LightAllocer {
-    /// Claims a render layer. If we're out, we'll get a dummy render layer that won't show up :(
-    pub(super) fn alloc(&mut self) -> usize {
-        self.unused_render_layers
-            .pop_front()
-            .unwrap_or(DUMMY_LAYER_USIZE)
+
+/// Fired when a light that is on and onscreen can't get room in the light atlas (or a sun can't get a render
+/// layer), meaning it won't be drawn. Lights closest to the `DynamicCamera` get priority, so this light may
+/// get room later.
+#[derive(Event, Clone, Debug)]
+pub struct LightAllocFailed {
+    pub light_eid: Entity,
+}
+
+/// A square piece of the light atlas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct AtlasTile {
+    /// The top left corner, in atlas pixels
+    pub(super) min: UVec2,
+    /// The side length, always a power of two
+    pub(super) size: u32,
+}
+impl AtlasTile {
+    /// Where the center of this tile is, in the coordinates the atlas camera sees
+    pub(super) fn center(&self, atlas_size: u32) -> Vec2 {
+        let half_atlas = atlas_size as f32 / 2.0;
+        let half_tile = self.size as f32 / 2.0;
+        Vec2::new(
+            self.min.x as f32 + half_tile - half_atlas,
+            half_atlas - self.min.y as f32 - half_tile,
+        )
     }
-    /// Returns a render layer back to the usable queue
-    pub fn free(&mut self, rl: usize) {
-        if rl != DUMMY_LAYER_USIZE {
-            self.unused_render_layers.push_back(rl);
+}
+
+/// Hands out tiles of the atlas. Tiles are split in four until they're just big enough,
+/// and merged back together once all four quarters are free again.
+#[derive(Clone, Debug)]
+struct AtlasTiles {
+    size: u32,
+    /// The top left corners of the free tiles, by size
+    free: HashMap<u32, Vec<UVec2>>,
+}
+impl AtlasTiles {
+    fn new(size: u32) -> Self {
+        let mut free = HashMap::default();
+        free.insert(size, vec![UVec2::ZERO]);
+        Self { size, free }
+    }
+    fn alloc(&mut self, size: u32) -> Option<AtlasTile> {
+        let size = size.next_power_of_two();
+        // Find the smallest free tile that's big enough...
+        let mut from = size;
+        while self.free.get(&from).is_none_or(|free| free.is_empty()) {
+            from *= 2;
+            if from > self.size {
+                return None;
+            }
+        }
+        let min = self.free.get_mut(&from).unwrap().pop().unwrap();
+        // ...then split it up until it isn't any bigger than it needs to be
+        while from > size {
+            from /= 2;
+            self.free.entry(from).or_default().extend([
+                min + UVec2::new(from, 0),
+                min + UVec2::new(0, from),
+                min + UVec2::splat(from),
+            ]);
         }
+        Some(AtlasTile { min, size })
+    }
+    fn free(&mut self, tile: AtlasTile) {
+        let AtlasTile { mut min, mut size } = tile;
+        while size < self.size {
+            let parent = (min / (size * 2)) * (size * 2);
+            let siblings = [
+                parent,
+                parent + UVec2::new(size, 0),
+                parent + UVec2::new(0, size),
+                parent + UVec2::splat(size),
+            ]
+            .into_iter()
+            .filter(|sibling| *sibling != min)
+            .collect::<Vec<_>>();
+            let free = self.free.entry(size).or_default();
+            if !siblings.iter().all(|sibling| free.contains(sibling)) {
+                break;
+            }
+            free.retain(|other| !siblings.contains(other));
+            min = parent;
+            size *= 2;
+        }
+        self.free.entry(size).or_default().push(min);
     }
 }
 
-/// Represents a claim to the resources needed for a light source
-#[derive(Clone)]
-pub(super) struct LightClaim {
-    /// The render layer that this light has sole control over
-    pub(super) rl_usize: usize,
-    /// The entity with the camera serving this light source
-    pub(super) camera_eid: Entity,
-    /// The final light mesh produced by this source, to be aggregated with all other lights in the light layer
-    pub(super) agg_mesh_eid: Entity,
-}
-impl Default for LightClaim {
-    fn default() -> Self {
+/// Facilitates assigning lights to pieces of the atlas (and suns to render layers) so that they don't
+/// interfere with each other
+#[derive(Resource, Clone, Debug)]
+pub(super) struct LightAllocer {
+    pub(super) atlas_render_layer: usize,
+    capacity: usize,
+    tiles: AtlasTiles,
+    unused_sun_render_layers: VecDeque<usize>,
+    /// Lights that are on but didn't get a tile last time we checked
+    starved: HashSet<Entity>,
+}
+impl LightAllocer {
+    pub(super) fn new(budget: LightBudget) -> Self {
+        assert!(
+            budget.base_render_layer > DUMMY_LAYER_USIZE,
+            "LightBudget: base_render_layer must be above the dummy render layer"
+        );
         Self {
-            rl_usize: DUMMY_LAYER_USIZE,
-            camera_eid: Entity::PLACEHOLDER,
-            agg_mesh_eid: Entity::PLACEHOLDER,
+            atlas_render_layer: budget.base_render_layer,
+            capacity: budget.capacity,
+            tiles: AtlasTiles::new(budget.atlas_size.max(1).next_power_of_two()),
+            unused_sun_render_layers: budget.render_layers().skip(1).collect(),
+            starved: default(),
         }
     }
+    pub(super) fn atlas_size(&self) -> u32 {
+        self.tiles.size
+    }
+    /// Gives back a tile claimed by a light
+    pub(super) fn free_tile(&mut self, tile: AtlasTile) {
+        self.tiles.free(tile);
+    }
+    /// Claims a render layer for a sun, if there are any left
+    pub(super) fn alloc_sun_render_layer(&mut self) -> Option<usize> {
+        self.unused_sun_render_layers.pop_front()
+    }
+    /// Gives back a render layer claimed by a sun
+    pub(super) fn free_sun_render_layer(&mut self, rl: usize) {
+        self.unused_sun_render_layers.push_back(rl);
+    }
+}
+
+/// How big (in pixels, on each side) the tile for a light of this radius needs to be
+fn tile_size(radius: f32) -> u32 {
+    ((radius.ceil() as u32).max(1) * 2).next_power_of_two()
+}
+
+/// How far (in pixels, along either axis) a body reaches from the light's center once it's turned by `angle`.
+/// The light's tile has to cover this as well as its radius, or the body would draw over its neighbours.
+fn body_reach(aabb: &Aabb, tran: &Transform, angle: f32) -> f32 {
+    let (center, half) = (aabb.center.xy(), aabb.half_extents.xy());
+    let rot = Vec2::from_angle(angle);
+    [
+        Vec2::new(-1.0, -1.0),
+        Vec2::new(-1.0, 1.0),
+        Vec2::new(1.0, -1.0),
+        Vec2::ONE,
+    ]
+    .into_iter()
+    .map(|corner| {
+        let point = tran.transform_point((center + corner * half).extend(0.0));
+        rot.rotate(point.truncate()).abs().max_element()
+    })
+    .fold(0.0, f32::max)
+}
+
+/// Represents a claim to the resources needed to draw a light.
+/// Only lights that are on, onscreen (and high enough priority) hold a claim, see `manage_light_claims`.
+/// Whatever draws the light (anim bodies, proc light body) is moved into the light's tile of the atlas,
+/// where the light's shadows can't touch any other light. Everything in the atlas is drawn by one camera,
+/// and a single mesh then copies every tile onto the right spot of the `LightLayer`.
+#[derive(Clone, Debug)]
+pub(super) struct LightClaim {
+    /// The piece of the atlas that this light has sole control over
+    pub(super) tile: AtlasTile,
+    /// The single mesh holding every shadow cast on this light, drawn on top of the light in its tile
+    pub(super) shadow_mesh_eid: Entity,
+    /// The underlying mesh asset, rewritten whenever shadows are recalculated
+    pub(super) shadow_mesh_hand: Handle<Mesh>,
 }
 impl LightClaim {
-    pub(super) fn alloc(world: &mut bevy::ecs::world::DeferredWorld) -> Self {
-        let res = world.resource::<LayersRes>().clone();
-
-        // Claim a render layer
-        let rl_usize = world.resource_mut::<LightAllocer>().alloc();
-
-        // Spawn a camera that is essentially scratch drawing space for light + cutouts
-        let image = blank_screen_image(&res, false);
-        let mut images = world.resource_mut::<Assets<Image>>();
-        let image_hand = images.add(image);
-        let camera_eid = world
-            .commands()
-            .spawn((
-                Name::new("LightCamera"),
-                Camera2d,
-                Camera {
-                    order: PRE_LIGHT_RENDER_ORDER as isize,
-                    target: RenderTarget::Image(image_hand.clone()),
-                    clear_color: ClearColorConfig::Custom(COLOR_NONE),
-                    ..default()
-                },
-                OrthographicProjection {
-                    near: ZIX_MIN,
-                    far: ZIX_MAX,
-                    scale: 1.0,
-                    ..OrthographicProjection::default_2d()
-                },
-                RenderLayers::from_layers(&[rl_usize]),
-                FollowDynamicCamera,
-            ))
-            .set_parent(res.root_eid())
-            .id();
+    /// Releases the claim from inside a hook
+    pub(super) fn free(&self, world: &mut bevy::ecs::world::DeferredWorld) {
+        world.resource_mut::<LightAllocer>().free_tile(self.tile);
+        if let Some(comms) = world.commands().get_entity(self.shadow_mesh_eid) {
+            comms.despawn_recursive();
+        }
+    }
+}
 
-        // Spawn the mesh which will apply proper cutout shader and chuck output into aggregate light layer
-        let mesh = Mesh::from(Rectangle::new(
-            res.screen_size.x as f32,
-            res.screen_size.y as f32,
-        ));
-        let mesh: Mesh2d = world.resource_mut::<Assets<Mesh>>().add(mesh).into();
-        let mat: MeshMaterial2d<LightCutoutMat> = world
-            .resource_mut::<Assets<LightCutoutMat>>()
-            .add(LightCutoutMat::new(image_hand.clone()))
-            .into();
-        let agg_mesh_eid = world
-            .commands()
+/// Everything needed to make and release light claims from a system
+#[derive(SystemParam)]
+pub(super) struct LightClaimParams<'w, 's> {
+    commands: Commands<'w, 's>,
+    res: Res<'w, LayersRes>,
+    allocer: ResMut<'w, LightAllocer>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+}
+impl LightClaimParams<'_, '_> {
+    /// Claims a tile of the atlas, and spawns the mesh that will hold the light's shadows.
+    /// Returns `None` if there's no room left in the atlas.
+    fn claim(&mut self, radius: f32) -> Option<LightClaim> {
+        let tile = self.allocer.tiles.alloc(tile_size(radius))?;
+        let shadow_mesh_hand = self.meshes.add(Mesh::from(Triangle2d::default()));
+        let shadow_mesh_eid = self
+            .commands
             .spawn((
-                Name::new("LightActualMesh"),
-                mesh,
-                mat,
-                Transform::default(),
+                Name::new("LightShadowMesh"),
+                Mesh2d(shadow_mesh_hand.clone()),
+                MeshMaterial2d(SHADOW_MAT_HAND.clone()),
+                Transform::from_translation(Vec3::Z * 100.0),
                 Visibility::Inherited,
-                LightLayer::RENDER_LAYERS,
+                RenderLayers::layer(self.allocer.atlas_render_layer),
+                NoFrustumCulling,
             ))
-            .set_parent(res.root_eid())
+            .set_parent(self.res.root_eid())
             .id();
+        Some(LightClaim {
+            tile,
+            shadow_mesh_eid,
+            shadow_mesh_hand,
+        })
+    }
+    /// Gives back the tile and despawns the shadow mesh. The mesh asset goes with its handle.
+    fn release(&mut self, claim: LightClaim) {
+        self.allocer.free_tile(claim.tile);
+        self.despawn(claim);
+    }
+    fn despawn(&mut self, claim: LightClaim) {
+        if let Some(comms) = self.commands.get_entity(claim.shadow_mesh_eid) {
+            comms.despawn_recursive();
+        }
+    }
+    /// Moves the light to a new tile if its radius no longer fits the one it has.
+    /// If there's no room for the new size, the claim is released and `None` is returned.
+    fn refit(&mut self, mut claim: LightClaim, radius: f32) -> Option<LightClaim> {
+        let size = tile_size(radius);
+        if claim.tile.size == size {
+            return Some(claim);
+        }
+        self.allocer.free_tile(claim.tile);
+        match self.allocer.tiles.alloc(size) {
+            Some(tile) => {
+                claim.tile = tile;
+                Some(claim)
+            }
+            None => {
+                self.despawn(claim);
+                None
+            }
+        }
+    }
+}
+
+/// Spawns the one camera that draws every light into the atlas, and the mesh that copies them into the light layer
+fn setup_light_atlas(
+    mut commands: Commands,
+    res: Res<LayersRes>,
+    allocer: Res<LightAllocer>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut cutout_mats: ResMut<Assets<LightCutoutMat>>,
+) {
+    images.insert(
+        LIGHT_ATLAS_HAND.id(),
+        blank_image(UVec2::splat(allocer.atlas_size())),
+    );
+    commands
+        .spawn((
+            Name::new("LightAtlasCamera"),
+            Camera2d,
+            Camera {
+                order: PRE_LIGHT_RENDER_ORDER as isize,
+                target: RenderTarget::Image(LIGHT_ATLAS_HAND),
+                clear_color: ClearColorConfig::Custom(COLOR_NONE),
+                ..default()
+            },
+            OrthographicProjection {
+                near: ZIX_MIN,
+                far: ZIX_MAX,
+                scale: 1.0,
+                ..OrthographicProjection::default_2d()
+            },
+            RenderLayers::layer(allocer.atlas_render_layer),
+        ))
+        .set_parent(res.root_eid());
+
+    meshes.insert(LIGHT_AGG_MESH_HAND.id(), AggMesh::default().into_mesh());
+    commands
+        .spawn((
+            Name::new("LightAggMesh"),
+            Mesh2d(LIGHT_AGG_MESH_HAND),
+            MeshMaterial2d(cutout_mats.add(LightCutoutMat::new(LIGHT_ATLAS_HAND))),
+            Transform::default(),
+            Visibility::Inherited,
+            LightLayer::RENDER_LAYERS,
+            NoFrustumCulling,
+        ))
+        .set_parent(res.root_eid());
+}
 
-        LightClaim {
-            rl_usize,
-            camera_eid,
-            agg_mesh_eid,
+/// Makes sure the lights that are on, onscreen and closest to the `DynamicCamera` are the ones holding claims.
+/// Lights that are off, offscreen (or lose out) give their claim back so they cost nothing to draw.
+/// Tiles are sized to fit both the light's radius and every body drawing it (hidden anim states too, so
+/// switching states never spills out of the tile).
+pub(super) fn manage_light_claims(
+    mut light_q: Query<(Entity, &Pos, &mut LightSource, Option<&Children>)>,
+    body_q: Query<(&Aabb, &Transform), Without<LightSource>>,
+    dynamic_camera_q: Query<&Pos, With<DynamicCamera>>,
+    mut rl_q: Query<&mut RenderLayers, Without<Camera>>,
+    mut claim_params: LightClaimParams,
+    mut failed_writer: EventWriter<LightAllocFailed>,
+) {
+    let focus = dynamic_camera_q
+        .get_single()
+        .map(|pos| pos.as_vec2())
+        .unwrap_or_default();
+    let mut ranked = light_q
+        .iter()
+        .filter(|(_, _, source, _)| source.radius.is_some() && source.onscreen)
+        .map(|(eid, pos, _, _)| (eid, pos.as_vec2().distance_squared(focus)))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let reach = |source: &LightSource, children: Option<&Children>| {
+        children
+            .into_iter()
+            .flatten()
+            .filter_map(|child| body_q.get(*child).ok())
+            .map(|(aabb, tran)| body_reach(aabb, tran, source.body_angle))
+            .fold(source.radius.unwrap_or_default(), f32::max)
+    };
+    let capacity = claim_params.allocer.capacity;
+    let winners = ranked
+        .iter()
+        .take(capacity)
+        .map(|(eid, _)| *eid)
+        .collect::<HashSet<_>>();
+
+    // Release claims first so the winners have room to go, and make sure the rest still fit
+    let atlas_rl = claim_params.allocer.atlas_render_layer;
+    let mut retargets = vec![];
+    for (eid, _, mut source, children) in &mut light_q {
+        let Some(claim) = source.claim.take() else {
+            continue;
+        };
+        if winners.contains(&eid) {
+            let reach = reach(&source, children);
+            source.claim = claim_params.refit(claim, reach);
+        } else {
+            claim_params.release(claim);
+        }
+        if source.claim.is_none() {
+            retargets.push((eid, atlas_rl, DUMMY_LAYER_USIZE));
         }
     }
-    pub(super) fn free(&self, world: &mut bevy::ecs::world::DeferredWorld) {
-        world.resource_mut::<LightAllocer>().free(self.rl_usize);
-        if let Some(comms) = world.commands().get_entity(self.camera_eid) {
-            comms.despawn_recursive();
+    for (eid, _) in ranked.iter().take(capacity) {
+        let Ok((_, _, mut source, children)) = light_q.get_mut(*eid) else {
+            continue;
+        };
+        if source.claim.is_none() {
+            let reach = reach(&source, children);
+            source.claim = claim_params.claim(reach);
+            if source.claim.is_some() {
+                retargets.push((*eid, DUMMY_LAYER_USIZE, atlas_rl));
+            }
         }
-        if let Some(comms) = world.commands().get_entity(self.agg_mesh_eid) {
-            comms.despawn_recursive();
+    }
+
+    // Whatever actually draws the light (anim bodies, proc light body) lives in the children
+    for (eid, old_rl, new_rl) in retargets {
+        let Ok((_, _, _, Some(children))) = light_q.get(eid) else {
+            continue;
+        };
+        let old_layers = RenderLayers::layer(old_rl);
+        for child in children {
+            if let Ok(mut layers) = rl_q.get_mut(*child) {
+                if *layers == old_layers {
+                    *layers = RenderLayers::layer(new_rl);
+                }
+            }
+        }
+    }
+
+    let starved = ranked
+        .iter()
+        .map(|(eid, _)| *eid)
+        .filter(|eid| {
+            light_q
+                .get(*eid)
+                .is_ok_and(|(_, _, source, _)| source.claim.is_none())
+        })
+        .collect::<HashSet<_>>();
+    for light_eid in starved.difference(&claim_params.allocer.starved) {
+        warn!(
+            "Out of room for lights ({capacity} lights, {}px atlas), light {light_eid} won't be drawn",
+            claim_params.allocer.atlas_size()
+        );
+        failed_writer.send(LightAllocFailed {
+            light_eid: *light_eid,
+        });
+    }
+    claim_params.allocer.starved = starved;
+}
+
+/// One quad per light, copying its tile of the atlas onto the light layer
+#[derive(Default)]
+struct AggMesh {
+    points: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    colors: Vec<[f32; 4]>,
+    tris: Vec<u32>,
+}
+impl AggMesh {
+    fn add_quad(&mut self, (min, max): (Vec2, Vec2), (uv_min, uv_max): (Vec2, Vec2), tint: Vec4) {
+        let first_ix = self.points.len() as u32;
+        self.tris.extend([
+            first_ix,
+            first_ix + 1,
+            first_ix + 2,
+            first_ix + 2,
+            first_ix + 3,
+            first_ix,
+        ]);
+        // Image rows go down, world y goes up
+        self.points.extend([
+            [min.x, min.y, 0.0],
+            [max.x, min.y, 0.0],
+            [max.x, max.y, 0.0],
+            [min.x, max.y, 0.0],
+        ]);
+        self.uvs.extend([
+            [uv_min.x, uv_max.y],
+            [uv_max.x, uv_max.y],
+            [uv_max.x, uv_min.y],
+            [uv_min.x, uv_min.y],
+        ]);
+        self.colors.extend([tint.to_array(); 4]);
+    }
+    fn into_mesh(mut self) -> Mesh {
+        if self.tris.is_empty() {
+            // No lights, but the mesh can't be empty, so draw a degenerate quad
+            self.add_quad(
+                (Vec2::ZERO, Vec2::ZERO),
+                (Vec2::ZERO, Vec2::ZERO),
+                Vec4::ZERO,
+            );
         }
+        let normals = vec![[0.0, 0.0, 1.0]; self.points.len()];
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.points)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, self.colors)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_indices(Indices::U32(self.tris))
+    }
+}
+
+/// Lines each light's shadows up with its tile of the atlas, and rebuilds the mesh that copies every tile
+/// onto the matching spot on screen
+fn place_light_claims(
+    light_q: Query<(&Pos, &LightSource)>,
+    dynamic_camera_q: Query<&Pos, With<DynamicCamera>>,
+    camera_shake: Res<CameraShake>,
+    allocer: Res<LightAllocer>,
+    mut tran_q: Query<&mut Transform>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let Ok(leader) = dynamic_camera_q.get_single() else {
+        return;
+    };
+    let shake_off = camera_shake.get_offset();
+    let view_center = (leader.as_vec2() + shake_off).round();
+    let atlas_size = allocer.atlas_size();
+    let mut agg = AggMesh::default();
+    for (pos, source) in &light_q {
+        let Some(claim) = source.claim.as_ref() else {
+            continue;
+        };
+        let center = pos.as_vec2().round();
+        let tile_center = claim.tile.center(atlas_size);
+        if let Ok(mut tran) = tran_q.get_mut(claim.shadow_mesh_eid) {
+            tran.translation.x = tile_center.x - center.x;
+            tran.translation.y = tile_center.y - center.y;
+        }
+        let half = Vec2::splat(claim.tile.size as f32 / 2.0);
+        let uv_min = claim.tile.min.as_vec2() / atlas_size as f32;
+        let uv_max = (claim.tile.min + claim.tile.size).as_vec2() / atlas_size as f32;
+        agg.add_quad(
+            (center - view_center - half, center - view_center + half),
+            (uv_min, uv_max),
+            color_as_vec4(source.tint),
+        );
+    }
+    meshes.insert(LIGHT_AGG_MESH_HAND.id(), agg.into_mesh());
+}
+
+/// Moves (and turns) whatever draws each light into the light's tile of the atlas.
+/// Runs after transforms are propagated, so only the drawn position changes, never the entity's `Transform`.
+fn place_light_bodies(
+    light_q: Query<(&Pos, &GlobalTransform, &LightSource, &Children)>,
+    mut body_q: Query<(&Transform, &RenderLayers, &mut GlobalTransform), Without<LightSource>>,
+    allocer: Res<LightAllocer>,
+) {
+    let atlas_layers = RenderLayers::layer(allocer.atlas_render_layer);
+    for (pos, light_gtran, source, children) in &light_q {
+        let Some(claim) = source.claim.as_ref() else {
+            continue;
+        };
+        // Keep the same sub-pixel offset the shadows get in `place_light_claims`
+        let anchor = claim.tile.center(allocer.atlas_size())
+            + (light_gtran.translation().truncate() - pos.as_vec2().round());
+        let placed = Transform::from_translation(anchor.extend(0.0))
+            .with_rotation(Quat::from_rotation_z(source.body_angle));
+        for child in children {
+            let Ok((tran, layers, mut gtran)) = body_q.get_mut(*child) else {
+                continue;
+            };
+            if *layers == atlas_layers {
+                *gtran = GlobalTransform::from(placed * *tran);
+            }
+        }
+    }
+}
+
+pub(super) fn register_light_alloc(app: &mut App, budget: LightBudget) {
+    app.insert_resource(LightAllocer::new(budget));
+    app.add_event::<LightAllocFailed>();
+    app.add_systems(
+        Startup,
+        setup_light_atlas.after(crate::plugin::init_root_eid),
+    );
+    app.add_systems(
+        Update,
+        manage_light_claims
+            .in_set(LightInteractionSet)
+            .before(super::light_interaction::block_lights),
+    );
+    app.add_systems(
+        Update,
+        place_light_claims
+            .in_set(LayersCameraSet)
+            .after(LightInteractionSet),
+    );
+    app.add_systems(
+        PostUpdate,
+        place_light_bodies
+            .after(TransformSystem::TransformPropagate)
+            .before(VisibilitySystems::CheckVisibility),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_split_down_to_size() {
+        let mut tiles = AtlasTiles::new(64);
+        let first = tiles.alloc(16).unwrap();
+        let second = tiles.alloc(16).unwrap();
+        assert_eq!(first.size, 16);
+        assert_eq!(second.size, 16);
+        assert_ne!(first.min, second.min);
+        // Odd sizes round up
+        assert_eq!(tiles.alloc(20).unwrap().size, 32);
+    }
+
+    #[test]
+    fn tiles_run_out() {
+        let mut tiles = AtlasTiles::new(64);
+        for _ in 0..4 {
+            assert!(tiles.alloc(32).is_some());
+        }
+        assert!(tiles.alloc(32).is_none());
+        assert!(tiles.alloc(128).is_none());
+    }
+
+    #[test]
+    fn freed_tiles_merge_back() {
+        let mut tiles = AtlasTiles::new(64);
+        let small = (0..16)
+            .map(|_| tiles.alloc(16).unwrap())
+            .collect::<Vec<_>>();
+        assert!(tiles.alloc(16).is_none());
+        for tile in small {
+            tiles.free(tile);
+        }
+        let whole = tiles.alloc(64).unwrap();
+        assert_eq!(whole.min, UVec2::ZERO);
+    }
+
+    #[test]
+    fn tiles_fit_bodies_bigger_than_the_radius() {
+        let sprite = Aabb::from_min_max(Vec3::new(-48.0, -16.0, 0.0), Vec3::new(48.0, 16.0, 0.0));
+        let reach = body_reach(&sprite, &Transform::default(), 0.0);
+        assert_eq!(reach, 48.0);
+        assert_eq!(tile_size(reach.max(16.0)), 128);
+        // Turning the body a quarter swaps its axes, offsets push it further out
+        let turned = body_reach(&sprite, &Transform::default(), std::f32::consts::FRAC_PI_2);
+        assert!((turned - 48.0).abs() < 1e-3);
+        let offset = body_reach(&sprite, &Transform::from_xyz(0.0, 40.0, 0.0), 0.0);
+        assert_eq!(offset, 56.0);
+    }
+
+    #[test]
+    fn suns_have_render_layers_of_their_own() {
+        let budget = LightBudget {
+            sun_capacity: 2,
+            ..default()
+        };
+        let mut allocer = LightAllocer::new(budget);
+        // Filling the atlas with point lights doesn't touch the suns' render layers
+        while allocer.tiles.alloc(16).is_some() {}
+        let first = allocer.alloc_sun_render_layer().unwrap();
+        let second = allocer.alloc_sun_render_layer().unwrap();
+        assert_eq!(allocer.alloc_sun_render_layer(), None);
+        for rl in [first, second] {
+            assert_ne!(rl, allocer.atlas_render_layer);
+            assert!(budget.render_layers().contains(&rl));
+        }
+        allocer.free_sun_render_layer(first);
+        assert_eq!(allocer.alloc_sun_render_layer(), Some(first));
+    }
+
+    #[test]
+    fn tile_centers_line_up_with_the_atlas_camera() {
+        let tile = AtlasTile {
+            min: UVec2::ZERO,
+            size: 16,
+        };
+        assert_eq!(tile.center(64), Vec2::new(-24.0, 24.0));
     }
 }
@@ -0,0 +1,331 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_2delight_physics::prelude::{HBox, Pos};
+
+use crate::LightInteractionSet;
+
+use super::{
+    light_caster::{polygon_contains, CasterShape, ShadowCaster},
+    light_contour::merge_hbox_edges,
+    light_visibility::SolidLine,
+};
+
+/// Casts shadows in the shape of an image's opaque pixels, instead of a box.
+/// Once the image loads, its outline is traced and this entity gets a matching `ShadowCaster`.
+/// If the entity already has a `ShadowCaster`, only its shapes are replaced, so opacity, tint and
+/// containment can be set on it as usual. The outline is retraced whenever the image changes.
+/// The image is assumed to be drawn centered on `Pos` at 1:1 scale, like a plain `Sprite`.
+/// NOTE: Holes completely surrounded by opaque pixels are filled in.
+#[derive(Component, Clone, Debug)]
+#[require(Pos)]
+pub struct AlphaShadowCaster {
+    image: Handle<Image>,
+    /// Pixels with alpha above this block light
+    threshold: f32,
+    /// Size (in pixels) of the blocks the image is traced in. Bigger is coarser, but cheaper to cast.
+    resolution: u32,
+}
+impl AlphaShadowCaster {
+    pub fn new(image: Handle<Image>) -> Self {
+        Self {
+            image,
+            threshold: 0.5,
+            resolution: 1,
+        }
+    }
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+    pub fn with_resolution(mut self, resolution: u32) -> Self {
+        self.resolution = resolution.max(1);
+        self
+    }
+}
+
+/// What an outline was traced from: the image, threshold (as bits) and resolution
+type OutlineKey = (AssetId<Image>, u32, u32);
+
+/// Outlines already traced, so many sprites sharing one image only trace it once
+#[derive(Resource, Default)]
+struct TracedOutlines {
+    map: HashMap<OutlineKey, Vec<CasterShape>>,
+    /// Which outline each caster currently has
+    applied: HashMap<Entity, OutlineKey>,
+}
+
+/// Joins up outline lines end to end into closed loops
+fn chain_loops(lines: Vec<SolidLine>) -> Vec<Vec<Vec2>> {
+    let vertex_key = |p: Vec2| ((p.x * 100.0).round() as i64, (p.y * 100.0).round() as i64);
+    let mut at_vertex: HashMap<(i64, i64), Vec<usize>> = HashMap::default();
+    for (ix, line) in lines.iter().enumerate() {
+        at_vertex.entry(vertex_key(line.a)).or_default().push(ix);
+        at_vertex.entry(vertex_key(line.b)).or_default().push(ix);
+    }
+    let mut used = vec![false; lines.len()];
+    let mut loops = vec![];
+    for first in 0..lines.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+        let start = lines[first].a;
+        let mut points = vec![start];
+        let mut current = lines[first].b;
+        while vertex_key(current) != vertex_key(start) {
+            points.push(current);
+            let Some(next) = at_vertex[&vertex_key(current)]
+                .iter()
+                .copied()
+                .find(|ix| !used[*ix])
+            else {
+                break;
+            };
+            used[next] = true;
+            current = if vertex_key(lines[next].a) == vertex_key(current) {
+                lines[next].b
+            } else {
+                lines[next].a
+            };
+        }
+        if points.len() >= 3 {
+            loops.push(points);
+        }
+    }
+    loops
+}
+
+/// Traces the outline of every block of the image that is (mostly) opaque, as closed polygons
+fn trace_outline(image: &Image, threshold: f32, resolution: u32) -> Vec<CasterShape> {
+    let size = image.size();
+    let blocks = (size + (resolution - 1)) / resolution;
+    let is_solid = |bx: u32, by: u32| {
+        let (mut solid, mut total) = (0, 0);
+        for y in (by * resolution)..((by + 1) * resolution).min(size.y) {
+            for x in (bx * resolution)..((bx + 1) * resolution).min(size.x) {
+                total += 1;
+                if image
+                    .get_color_at(x, y)
+                    .is_ok_and(|color| color.alpha() > threshold)
+                {
+                    solid += 1;
+                }
+            }
+        }
+        solid * 2 > total
+    };
+
+    // Every run of solid blocks in a row becomes a box, then the boxes get outlined together
+    let half_size = size.as_vec2() / 2.0;
+    let mut runs = vec![];
+    for by in 0..blocks.y {
+        let mut bx = 0;
+        while bx < blocks.x {
+            if !is_solid(bx, by) {
+                bx += 1;
+                continue;
+            }
+            let start = bx;
+            while bx < blocks.x && is_solid(bx, by) {
+                bx += 1;
+            }
+            let min = UVec2::new(start, by) * resolution;
+            let max = (UVec2::new(bx, by + 1) * resolution).min(size);
+            let run_size = max - min;
+            // Image rows go down, world y goes up
+            let center = Vec2::new(
+                min.x as f32 + run_size.x as f32 / 2.0 - half_size.x,
+                half_size.y - (min.y as f32 + run_size.y as f32 / 2.0),
+            );
+            runs.push(HBox::new(run_size.x, run_size.y).with_offset(center.x, center.y));
+        }
+    }
+    let loops = chain_loops(merge_hbox_edges(&runs.iter().collect::<Vec<_>>(), None));
+    // Loops inside other loops are the edges of holes, which get filled in
+    let is_hole = |points: &Vec<Vec2>| {
+        let mid = (points[0] + points[1]) / 2.0;
+        loops
+            .iter()
+            .any(|other| other != points && polygon_contains(other, mid))
+    };
+    loops
+        .iter()
+        .filter(|points| !is_hole(points))
+        .cloned()
+        .map(CasterShape::Polygon)
+        .collect()
+}
+
+/// Forgets outlines of images that have changed (or gone), so the casters using them get retraced
+fn forget_stale_outlines(
+    mut events: EventReader<AssetEvent<Image>>,
+    mut traced: ResMut<TracedOutlines>,
+) {
+    for event in events.read() {
+        let (AssetEvent::Modified { id } | AssetEvent::Removed { id }) = event else {
+            continue;
+        };
+        traced.map.retain(|key, _| key.0 != *id);
+        traced.applied.retain(|_, key| key.0 != *id);
+    }
+}
+
+fn trace_alpha_shadow_casters(
+    mut commands: Commands,
+    mut caster_q: Query<(Entity, Ref<AlphaShadowCaster>, Option<&mut ShadowCaster>)>,
+    images: Res<Assets<Image>>,
+    mut traced: ResMut<TracedOutlines>,
+) {
+    for (eid, caster, shadow_caster) in &mut caster_q {
+        let key = (
+            caster.image.id(),
+            caster.threshold.to_bits(),
+            caster.resolution,
+        );
+        if !caster.is_changed() && traced.applied.get(&eid) == Some(&key) {
+            continue;
+        }
+        let shapes = match traced.map.get(&key) {
+            Some(shapes) => shapes.clone(),
+            None => {
+                let Some(image) = images.get(caster.image.id()) else {
+                    // Not loaded yet (or gone), so drop any stale outline and try again next frame
+                    if let Some(mut shadow_caster) = shadow_caster {
+                        if !shadow_caster.get_shapes().is_empty() {
+                            shadow_caster.set_shapes([]);
+                        }
+                    }
+                    traced.applied.remove(&eid);
+                    continue;
+                };
+                let shapes = trace_outline(image, caster.threshold, caster.resolution);
+                traced.map.insert(key, shapes.clone());
+                shapes
+            }
+        };
+        match shadow_caster {
+            Some(mut shadow_caster) => shadow_caster.set_shapes(shapes),
+            None => {
+                commands
+                    .entity(eid)
+                    .insert(ShadowCaster::from_shapes(shapes));
+            }
+        }
+        traced.applied.insert(eid, key);
+    }
+    let TracedOutlines { applied, .. } = traced.as_mut();
+    applied.retain(|eid, _| caster_q.contains(*eid));
+}
+
+pub(super) fn register_light_alpha_caster(app: &mut App) {
+    app.insert_resource(TracedOutlines::default());
+    app.add_systems(
+        Update,
+        (forget_stale_outlines, trace_alpha_shadow_casters)
+            .chain()
+            .before(LightInteractionSet),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    };
+
+    use super::*;
+
+    /// A `size` square image, clear except for an opaque `solid` square in the middle
+    fn square_image(size: u32, solid: u32) -> Image {
+        let mut image = Image::new_fill(
+            Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 0],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        let start = (size - solid) / 2;
+        for y in start..(start + solid) {
+            for x in start..(start + solid) {
+                image.set_color_at(x, y, Color::WHITE).unwrap();
+            }
+        }
+        image
+    }
+
+    fn corners(shapes: &[CasterShape]) -> Vec<Vec2> {
+        let mut corners: Vec<Vec2> = vec![];
+        for line in shapes.iter().flat_map(|shape| shape.to_solid_lines()) {
+            for point in [line.a, line.b] {
+                if !corners.iter().any(|corner| corner.distance(point) < 0.01) {
+                    corners.push(point);
+                }
+            }
+        }
+        corners
+    }
+
+    #[test]
+    fn square_image_traces_to_four_corners() {
+        let shapes = trace_outline(&square_image(8, 4), 0.5, 1);
+        let corners = corners(&shapes);
+        assert_eq!(corners.len(), 4);
+        for expected in [
+            Vec2::new(-2.0, -2.0),
+            Vec2::new(2.0, -2.0),
+            Vec2::new(2.0, 2.0),
+            Vec2::new(-2.0, 2.0),
+        ] {
+            assert!(corners
+                .iter()
+                .any(|corner| corner.distance(expected) < 0.01));
+        }
+    }
+
+    #[test]
+    fn outlines_are_closed_polygons() {
+        let shapes = trace_outline(&square_image(8, 4), 0.5, 1);
+        assert_eq!(shapes.len(), 1);
+        let CasterShape::Polygon(points) = &shapes[0] else {
+            panic!("Expected a polygon, got {:?}", shapes[0]);
+        };
+        assert_eq!(points.len(), 4);
+        assert!(shapes[0].contains(Vec2::ZERO));
+        assert!(!shapes[0].contains(Vec2::new(3.0, 0.0)));
+    }
+
+    #[test]
+    fn holes_are_filled_in() {
+        let mut image = square_image(8, 6);
+        for (x, y) in [(3, 3), (3, 4), (4, 3), (4, 4)] {
+            image.set_color_at(x, y, Color::NONE).unwrap();
+        }
+        let shapes = trace_outline(&image, 0.5, 1);
+        assert_eq!(shapes.len(), 1);
+        assert!(shapes[0].contains(Vec2::ZERO));
+    }
+
+    #[test]
+    fn clear_image_traces_nothing() {
+        let shapes = trace_outline(&square_image(8, 0), 0.5, 1);
+        assert!(shapes.is_empty());
+    }
+
+    #[test]
+    fn coarse_resolution_rounds_to_blocks() {
+        // Pixels 2, 3 and 4 are solid, so only the 2x2 block covering pixels 2 and 3 is mostly solid
+        let shapes = trace_outline(&square_image(8, 3), 0.5, 2);
+        let corners = corners(&shapes);
+        assert_eq!(corners.len(), 4);
+        assert!(corners
+            .iter()
+            .any(|corner| corner.distance(Vec2::new(-2.0, 2.0)) < 0.01));
+        assert!(corners
+            .iter()
+            .any(|corner| corner.distance(Vec2::new(0.0, 0.0)) < 0.01));
+    }
+}
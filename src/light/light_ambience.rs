@@ -0,0 +1,149 @@
+use bevy::{color::Mix, prelude::*, utils::HashMap};
+
+use crate::layer::LayerDefn;
+
+use super::light_mat::LightApplyMat;
+
+#[derive(Clone, Debug, Reflect)]
+struct AmbienceFade {
+    from: Color,
+    to: Color,
+    time_left: f32,
+    duration: f32,
+}
+
+#[derive(Clone, Debug, Reflect)]
+struct AmbienceSpec {
+    current: Color,
+    fade: Option<AmbienceFade>,
+    dirty: bool,
+}
+impl AmbienceSpec {
+    fn new(color: Color) -> Self {
+        Self {
+            current: color,
+            fade: None,
+            dirty: true,
+        }
+    }
+}
+
+/// Controls the base (ambient) light of each lit layer at runtime.
+/// Layers start with the `base_color` given in their `LayerOutputMode::Lit`.
+#[derive(Resource, Debug, Default, Reflect)]
+pub struct LayerAmbience {
+    specs: HashMap<u32, AmbienceSpec>,
+}
+impl LayerAmbience {
+    /// The current ambient color of a lit layer, `None` if the layer isn't lit
+    pub fn get<L: LayerDefn>(&self) -> Option<Color> {
+        self.specs.get(&L::KEY).map(|spec| spec.current)
+    }
+
+    /// The spec of a lit layer, warning (and returning `None`) if the layer isn't lit
+    fn lit_spec<L: LayerDefn>(&mut self) -> Option<&mut AmbienceSpec> {
+        let spec = self.specs.get_mut(&L::KEY);
+        if spec.is_none() {
+            warn!(
+                "Layer {:?} isn't lit (or hasn't been added), so it has no ambience to change",
+                L::default()
+            );
+        }
+        spec
+    }
+
+    /// Immediately sets the ambient color of a lit layer, cancelling any fade in progress
+    /// NOTE: Warns and does nothing if the layer isn't lit
+    pub fn set<L: LayerDefn>(&mut self, color: Color) {
+        if let Some(spec) = self.lit_spec::<L>() {
+            *spec = AmbienceSpec::new(color);
+        }
+    }
+
+    /// Smoothly moves the ambient color of a lit layer from its current value to `color` over `time` seconds
+    /// NOTE: Warns and does nothing if the layer isn't lit
+    pub fn fade_to<L: LayerDefn>(&mut self, color: Color, time: f32) {
+        let Some(spec) = self.lit_spec::<L>() else {
+            return;
+        };
+        spec.fade = Some(AmbienceFade {
+            from: spec.current,
+            to: color,
+            time_left: time,
+            duration: time,
+        });
+    }
+
+    /// Smoothly moves the ambient color of a lit layer from `from` to `to` over `time` seconds
+    /// NOTE: Warns and does nothing if the layer isn't lit
+    pub fn fade_between<L: LayerDefn>(&mut self, from: Color, to: Color, time: f32) {
+        let Some(spec) = self.lit_spec::<L>() else {
+            return;
+        };
+        *spec = AmbienceSpec::new(from);
+        spec.fade = Some(AmbienceFade {
+            from,
+            to,
+            time_left: time,
+            duration: time,
+        });
+    }
+
+    /// Gives a lit layer its starting ambience. Called when the layer is added, so before any `Startup` systems.
+    pub(crate) fn init(&mut self, key: u32, color: Color) {
+        self.specs.insert(key, AmbienceSpec::new(color));
+    }
+}
+
+/// Marks the output mesh of a lit layer, so we know which ambience spec drives its material
+#[derive(Component)]
+pub(crate) struct LitLayerOutput {
+    pub(crate) key: u32,
+}
+
+fn update_layer_ambience(
+    mut ambience: ResMut<LayerAmbience>,
+    time: Res<Time>,
+    output_q: Query<(&LitLayerOutput, &MeshMaterial2d<LightApplyMat>)>,
+    mut mats: ResMut<Assets<LightApplyMat>>,
+) {
+    // Advance any fades
+    for spec in ambience.specs.values_mut() {
+        let Some(fade) = spec.fade.as_mut() else {
+            continue;
+        };
+        fade.time_left -= time.delta_secs();
+        let frac = if fade.duration > 0.0 {
+            (1.0 - fade.time_left / fade.duration).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        spec.current = fade.from.to_linear().mix(&fade.to.to_linear(), frac).into();
+        spec.dirty = true;
+        if fade.time_left <= 0.0 {
+            spec.fade = None;
+        }
+    }
+
+    // Write any changes through to the materials
+    for (output, mat_hand) in &output_q {
+        let Some(spec) = ambience.specs.get(&output.key) else {
+            continue;
+        };
+        if !spec.dirty {
+            continue;
+        }
+        if let Some(mat) = mats.get_mut(mat_hand.id()) {
+            mat.set_base(spec.current);
+        }
+    }
+    for spec in ambience.specs.values_mut() {
+        spec.dirty = false;
+    }
+}
+
+pub(super) fn register_light_ambience(app: &mut App) {
+    app.init_resource::<LayerAmbience>();
+
+    app.add_systems(PostUpdate, update_layer_ambience);
+}
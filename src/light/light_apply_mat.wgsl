@@ -13,6 +13,9 @@ var light_splr: sampler;
 @group(2) @binding(5)
 var<uniform> base_light: vec4<f32>;
 
+@group(2) @binding(6)
+var<uniform> light_scale: vec2<f32>;
+
 fn vec_min(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
     var result = a;
     if (b.x < a.x) {
@@ -33,7 +36,9 @@ fn vec_min(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     let original = textureSample(image_texture, image_splr, in.uv);
-    let active_light = textureSample(light_texture, light_splr, in.uv);
+    // The light target is bigger than the screen by the light margin, so only sample the middle of it
+    let light_uv = (in.uv - vec2<f32>(0.5)) * light_scale + vec2<f32>(0.5);
+    let active_light = textureSample(light_texture, light_splr, light_uv);
     let total_light = vec_min(base_light + active_light, vec4<f32>(1.0));
     return vec4<f32>(
         original[0] * total_light[0],
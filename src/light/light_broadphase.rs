@@ -0,0 +1,326 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_2delight_physics::prelude::{HBox, Pos, StaticTx};
+
+use crate::LightInteractionSet;
+
+use super::{
+    light_caster::{CasterShape, LightContainment, NoStaticTxShadow, ShadowCaster, ShadowSettings},
+    light_contour::merge_hbox_edges,
+    light_visibility::SolidLine,
+};
+
+/// How big (in pixels) each cell of the blocker grid is
+const BLOCKER_CELL_SIZE: f32 = 64.0;
+
+/// Blockers are keyed by where their hboxes came from, since an entity may have both
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum BlockerKey {
+    StaticTx(Entity),
+    Caster(Entity),
+}
+
+struct GridBlocker {
+    /// Already translated to world space. Static blockers are all `CasterShape::HBox`
+    shapes: Vec<CasterShape>,
+    /// How much of each color channel gets through, `None` being a solid blocker
+    transmittance: Option<Vec3>,
+    containment: LightContainment,
+    cells: Vec<IVec2>,
+}
+
+impl GridBlocker {
+    fn hboxes(&self) -> impl Iterator<Item = &HBox> {
+        self.shapes.iter().filter_map(|shape| match shape {
+            CasterShape::HBox(hbox) => Some(hbox),
+            _ => None,
+        })
+    }
+}
+
+/// A `ShadowCaster` shape near a light, along with everything about its caster the light cares about
+pub(super) struct NearbyCaster<'a> {
+    pub(super) eid: Entity,
+    pub(super) shape: &'a CasterShape,
+    /// How much of each color channel gets through, `None` being a solid blocker
+    pub(super) transmittance: Option<Vec3>,
+    pub(super) containment: LightContainment,
+}
+
+#[derive(Default)]
+struct GridCell {
+    keys: Vec<BlockerKey>,
+    /// The grid tick at which something last entered or left this cell
+    changed_at: u64,
+    /// The outline of every static hbox in this cell, clipped to the cell.
+    /// Tiles next to each other share a single outline instead of each casting their own edges.
+    static_lines: Vec<SolidLine>,
+}
+
+/// A uniform grid over every hbox that casts shadows, so lights only need to look at nearby blockers.
+/// Kept up to date incrementally as blockers move, appear or disappear.
+#[derive(Resource, Default)]
+pub(super) struct BlockerGrid {
+    /// Bumped every frame, and stamped onto cells when something in them changes
+    tick: u64,
+    blockers: HashMap<BlockerKey, GridBlocker>,
+    cells: HashMap<IVec2, GridCell>,
+    /// Cells whose static outline needs recalculating
+    dirty: HashSet<IVec2>,
+}
+impl BlockerGrid {
+    fn cell_range(min: Vec2, max: Vec2) -> impl Iterator<Item = IVec2> {
+        let min = (min / BLOCKER_CELL_SIZE).floor().as_ivec2();
+        let max = (max / BLOCKER_CELL_SIZE).floor().as_ivec2();
+        (min.x..=max.x).flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+    }
+
+    /// Marks a cell as changed by `key`. Only static blockers have outlines, so only they dirty the cell.
+    fn touch(&mut self, cell: IVec2, key: BlockerKey) -> &mut GridCell {
+        let tick = self.tick;
+        if matches!(key, BlockerKey::StaticTx(_)) {
+            self.dirty.insert(cell);
+        }
+        let cell = self.cells.entry(cell).or_default();
+        cell.changed_at = tick;
+        cell
+    }
+
+    /// Recalculates the static outline of every cell that changed
+    fn rebuild_dirty_outlines(&mut self) {
+        for cell_ix in std::mem::take(&mut self.dirty) {
+            let Some(cell) = self.cells.get(&cell_ix) else {
+                continue;
+            };
+            let hboxes = cell
+                .keys
+                .iter()
+                .filter(|key| matches!(key, BlockerKey::StaticTx(_)))
+                .filter_map(|key| self.blockers.get(key))
+                .flat_map(|blocker| blocker.hboxes())
+                .collect::<Vec<_>>();
+            let min = cell_ix.as_vec2() * BLOCKER_CELL_SIZE;
+            let lines = merge_hbox_edges(&hboxes, Some((min, min + BLOCKER_CELL_SIZE)));
+            if let Some(cell) = self.cells.get_mut(&cell_ix) {
+                cell.static_lines = lines;
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: BlockerKey,
+        shapes: Vec<CasterShape>,
+        transmittance: Option<Vec3>,
+        containment: LightContainment,
+    ) {
+        self.remove(key);
+        let mut cells = shapes
+            .iter()
+            .flat_map(|shape| {
+                let (min, max) = shape.bounds();
+                Self::cell_range(min, max)
+            })
+            .collect::<Vec<_>>();
+        cells.sort_by_key(|cell| (cell.x, cell.y));
+        cells.dedup();
+        for cell in cells.iter() {
+            self.touch(*cell, key).keys.push(key);
+        }
+        self.blockers.insert(
+            key,
+            GridBlocker {
+                shapes,
+                transmittance,
+                containment,
+                cells,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: BlockerKey) {
+        let Some(blocker) = self.blockers.remove(&key) else {
+            return;
+        };
+        for cell in blocker.cells {
+            self.touch(cell, key).keys.retain(|other| *other != key);
+        }
+    }
+
+    fn remove_all_static(&mut self) {
+        let keys = self
+            .blockers
+            .keys()
+            .filter(|key| matches!(key, BlockerKey::StaticTx(_)))
+            .copied()
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.remove(key);
+        }
+    }
+
+    /// Every blocker that might overlap the square of half size `extent` around `source`.
+    /// Blockers spanning several cells only show up once.
+    fn blockers_near(&self, source: Vec2, extent: f32) -> Vec<BlockerKey> {
+        let mut keys = Self::cell_range(source - extent, source + extent)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flat_map(|cell| cell.keys.iter().copied())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    /// Every static (`StaticTx`) hbox that might overlap the square of half size `extent` around `source`
+    pub(super) fn static_hboxes_near(&self, source: Vec2, extent: f32) -> Vec<&HBox> {
+        self.blockers_near(source, extent)
+            .into_iter()
+            .filter(|key| matches!(key, BlockerKey::StaticTx(_)))
+            .filter_map(|key| self.blockers.get(&key))
+            .flat_map(|blocker| blocker.hboxes())
+            .collect()
+    }
+
+    /// The merged outline of every static hbox in the cells covering the square of half size `extent`
+    /// around `source`
+    pub(super) fn static_lines_near(
+        &self,
+        source: Vec2,
+        extent: f32,
+    ) -> impl Iterator<Item = &SolidLine> {
+        Self::cell_range(source - extent, source + extent)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flat_map(|cell| cell.static_lines.iter())
+    }
+
+    /// Every `ShadowCaster` shape that might overlap the square of half size `extent` around `source`
+    pub(super) fn caster_shapes_near(&self, source: Vec2, extent: f32) -> Vec<NearbyCaster<'_>> {
+        self.blockers_near(source, extent)
+            .into_iter()
+            .filter_map(|key| match key {
+                BlockerKey::Caster(eid) => Some((eid, self.blockers.get(&key)?)),
+                BlockerKey::StaticTx(_) => None,
+            })
+            .flat_map(|(eid, blocker)| {
+                blocker.shapes.iter().map(move |shape| NearbyCaster {
+                    eid,
+                    shape,
+                    transmittance: blocker.transmittance,
+                    containment: blocker.containment,
+                })
+            })
+            .collect()
+    }
+
+    /// The current tick. Anything computed now stays valid until `last_changed_near` says otherwise.
+    pub(super) fn get_tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// The last tick at which any blocker near the square of half size `extent` around `source` changed
+    pub(super) fn last_changed_near(&self, source: Vec2, extent: f32) -> u64 {
+        Self::cell_range(source - extent, source + extent)
+            .filter_map(|cell| self.cells.get(&cell))
+            .map(|cell| cell.changed_at)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+pub(super) fn update_blocker_grid(
+    mut grid: ResMut<BlockerGrid>,
+    static_q: Query<(Entity, Ref<Pos>, Ref<StaticTx>), Without<NoStaticTxShadow>>,
+    caster_q: Query<(Entity, Ref<Pos>, Ref<ShadowCaster>)>,
+    opted_out_q: Query<Entity, (With<StaticTx>, Added<NoStaticTxShadow>)>,
+    (mut removed_static, mut removed_caster, mut removed_opt_out): (
+        RemovedComponents<StaticTx>,
+        RemovedComponents<ShadowCaster>,
+        RemovedComponents<NoStaticTxShadow>,
+    ),
+    settings: Res<ShadowSettings>,
+) {
+    // Cells touched from here on are stamped with the new tick, so anything computed before is stale
+    grid.tick += 1;
+
+    let rebuild_static = settings.is_changed();
+    if rebuild_static {
+        grid.remove_all_static();
+    }
+    for eid in removed_static.read().chain(opted_out_q.iter()) {
+        grid.remove(BlockerKey::StaticTx(eid));
+    }
+    for eid in removed_caster.read() {
+        grid.remove(BlockerKey::Caster(eid));
+    }
+    let opted_back_in = removed_opt_out.read().collect::<Vec<_>>();
+
+    if settings.static_tx_casts {
+        for (eid, pos, stx) in &static_q {
+            if rebuild_static
+                || pos.is_changed()
+                || stx.is_changed()
+                || opted_back_in.contains(&eid)
+            {
+                let shapes = stx.get_thboxes(*pos).into_iter().map(CasterShape::HBox);
+                grid.insert(
+                    BlockerKey::StaticTx(eid),
+                    shapes.collect(),
+                    None,
+                    LightContainment::IgnoreCaster,
+                );
+            }
+        }
+    }
+    for (eid, pos, caster) in &caster_q {
+        if pos.is_changed() || caster.is_changed() {
+            grid.insert(
+                BlockerKey::Caster(eid),
+                caster.get_tshapes(*pos),
+                caster.get_transmittance(),
+                caster.get_containment(),
+            );
+        }
+    }
+    grid.rebuild_dirty_outlines();
+}
+
+pub(super) fn register_light_broadphase(app: &mut App) {
+    app.insert_resource(BlockerGrid::default());
+    app.add_systems(
+        Update,
+        update_blocker_grid
+            .in_set(LightInteractionSet)
+            .before(super::light_interaction::block_lights),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hbox_at(x: f32) -> Vec<CasterShape> {
+        vec![CasterShape::HBox(HBox::new(8, 8).with_offset(x, 0.0))]
+    }
+
+    #[test]
+    fn moving_casters_leave_static_outlines_alone() {
+        let mut grid = BlockerGrid::default();
+        let caster = BlockerKey::Caster(Entity::from_raw(1));
+        grid.insert(caster, hbox_at(0.0), None, LightContainment::IgnoreCaster);
+        grid.tick += 1;
+        grid.insert(caster, hbox_at(100.0), None, LightContainment::IgnoreCaster);
+        assert!(grid.dirty.is_empty());
+        // Lights near where it left and where it went still see the change
+        assert_eq!(grid.last_changed_near(Vec2::ZERO, 4.0), grid.tick);
+        assert_eq!(
+            grid.last_changed_near(Vec2::new(100.0, 0.0), 4.0),
+            grid.tick
+        );
+
+        let wall = BlockerKey::StaticTx(Entity::from_raw(2));
+        grid.insert(wall, hbox_at(0.0), None, LightContainment::IgnoreCaster);
+        assert!(!grid.dirty.is_empty());
+    }
+}
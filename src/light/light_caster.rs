@@ -0,0 +1,329 @@
+use bevy::prelude::*;
+use bevy_2delight_physics::prelude::{HBox, Pos};
+
+use super::light_visibility::SolidLine;
+
+/// How many straight edges a circle gets when casting shadows
+const CIRCLE_SEGMENTS: usize = 16;
+
+/// A shape that casts shadows. Positions are relative to the caster's `Pos`.
+#[derive(Clone, Debug, Reflect)]
+pub enum CasterShape {
+    HBox(HBox),
+    /// A polygon (convex or not), given by its corners in order (either direction). Its edges shouldn't cross.
+    Polygon(Vec<Vec2>),
+    Circle {
+        offset: Vec2,
+        radius: f32,
+    },
+    /// A single line, blocking light from both sides
+    Segment {
+        a: Vec2,
+        b: Vec2,
+    },
+}
+impl CasterShape {
+    pub fn circle(radius: f32) -> Self {
+        Self::Circle {
+            offset: Vec2::ZERO,
+            radius,
+        }
+    }
+    pub fn segment(a: Vec2, b: Vec2) -> Self {
+        Self::Segment { a, b }
+    }
+    /// A rectangle of the given size, rotated counter-clockwise by `angle` radians around its center
+    pub fn rotated_rect(size: Vec2, angle: f32) -> Self {
+        let half = size / 2.0;
+        let rot = Vec2::from_angle(angle);
+        Self::Polygon(
+            [
+                Vec2::new(-half.x, -half.y),
+                Vec2::new(half.x, -half.y),
+                Vec2::new(half.x, half.y),
+                Vec2::new(-half.x, half.y),
+            ]
+            .into_iter()
+            .map(|corner| rot.rotate(corner))
+            .collect(),
+        )
+    }
+
+    pub(super) fn translated(&self, offset: Vec2) -> Self {
+        match self {
+            Self::HBox(hbox) => Self::HBox(hbox.translated(offset.x, offset.y)),
+            Self::Polygon(points) => Self::Polygon(points.iter().map(|p| *p + offset).collect()),
+            Self::Circle {
+                offset: center,
+                radius,
+            } => Self::Circle {
+                offset: *center + offset,
+                radius: *radius,
+            },
+            Self::Segment { a, b } => Self::Segment {
+                a: *a + offset,
+                b: *b + offset,
+            },
+        }
+    }
+
+    /// The (min, max) corners of the box around this shape
+    pub(super) fn bounds(&self) -> (Vec2, Vec2) {
+        match self {
+            Self::HBox(hbox) => (
+                Vec2::new(hbox.min_x(), hbox.min_y()),
+                Vec2::new(hbox.max_x(), hbox.max_y()),
+            ),
+            Self::Polygon(points) => points
+                .iter()
+                .fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), p| {
+                    (min.min(*p), max.max(*p))
+                }),
+            Self::Circle { offset, radius } => (*offset - *radius, *offset + *radius),
+            Self::Segment { a, b } => (a.min(*b), a.max(*b)),
+        }
+    }
+
+    /// Is this point inside (or right on the edge of) the shape?
+    pub(super) fn contains(&self, point: Vec2) -> bool {
+        match self {
+            Self::HBox(hbox) => hbox.manhattan_distance_to_point(point) <= 0.1,
+            Self::Polygon(points) if points.is_empty() => false,
+            Self::Polygon(points) => {
+                let on_edge = self.to_solid_lines().iter().any(|line| {
+                    let seg = line.b - line.a;
+                    let t = (point - line.a).dot(seg) / seg.length_squared().max(f32::EPSILON);
+                    point.distance(line.a + seg * t.clamp(0.0, 1.0)) <= 0.1
+                });
+                on_edge || polygon_contains(points, point)
+            }
+            Self::Circle { offset, radius } => point.distance(*offset) <= *radius + 0.1,
+            // Nothing can be inside a line
+            Self::Segment { .. } => false,
+        }
+    }
+
+    pub(super) fn to_solid_lines(&self) -> Vec<SolidLine> {
+        match self {
+            Self::HBox(_) => {
+                let (min, max) = self.bounds();
+                Self::Polygon(vec![
+                    min,
+                    Vec2::new(min.x, max.y),
+                    max,
+                    Vec2::new(max.x, min.y),
+                ])
+                .to_solid_lines()
+            }
+            Self::Polygon(points) => points
+                .iter()
+                .zip(points.iter().cycle().skip(1))
+                .take(points.len())
+                .map(|(a, b)| SolidLine { a: *a, b: *b })
+                .collect(),
+            Self::Circle { offset, radius } => Self::Polygon(
+                (0..CIRCLE_SEGMENTS)
+                    .map(|ix| {
+                        let angle = std::f32::consts::TAU * ix as f32 / CIRCLE_SEGMENTS as f32;
+                        *offset + Vec2::from_angle(angle) * *radius
+                    })
+                    .collect(),
+            )
+            .to_solid_lines(),
+            Self::Segment { a, b } => vec![SolidLine { a: *a, b: *b }],
+        }
+    }
+}
+
+/// Is the point inside the polygon? Counts how many edges a ray going right from the point crosses,
+/// so works for any polygon whose edges don't cross. Points right on an edge may go either way.
+pub(super) fn polygon_contains(points: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y) {
+            let cross_x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if point.x < cross_x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// What happens to a light whose source is inside a caster
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum LightContainment {
+    /// The caster doesn't block the light at all. Good for pass-up platforms.
+    #[default]
+    IgnoreCaster,
+    /// The caster blocks the light completely (a lamp in a closed chest)
+    FullyOccluded,
+    /// The light fills the inside of the caster, but nothing outside it
+    InteriorOnly,
+}
+
+/// Makes an entity cast shadows, independent of any physics it may (or may not) have.
+/// This is also how moving bodies (players, enemies) cast shadows: `StaticRx` and `TriggerRx` don't expose
+/// their hitboxes, so give the body a `ShadowCaster` with the same hbox(es) and it will follow its `Pos`.
+/// A caster never blocks lights on its own entity (or anywhere below it in the hierarchy), so a player's
+/// lantern isn't swallowed by the player, even when it hangs off a hand.
+#[derive(Component, Clone, Debug)]
+#[require(Pos)]
+pub struct ShadowCaster {
+    shapes: Vec<CasterShape>,
+    /// How much light this blocks. 1.0 (the default) casts a solid shadow, less lets some through.
+    opacity: f32,
+    /// The color light passing through this becomes (think stained glass). Only matters if not fully opaque.
+    tint: Color,
+    /// What this does to lights inside it, unless the light says otherwise (see `LightShadows`)
+    containment: LightContainment,
+}
+impl ShadowCaster {
+    pub fn single(hbox: HBox) -> Self {
+        Self::new([hbox])
+    }
+    pub fn new<I: IntoIterator<Item = HBox>>(hboxes: I) -> Self {
+        Self::from_shapes(hboxes.into_iter().map(CasterShape::HBox))
+    }
+    pub fn from_shape(shape: CasterShape) -> Self {
+        Self::from_shapes([shape])
+    }
+    pub fn from_shapes<I: IntoIterator<Item = CasterShape>>(shapes: I) -> Self {
+        Self {
+            shapes: shapes.into_iter().collect(),
+            opacity: 1.0,
+            tint: Color::WHITE,
+            containment: default(),
+        }
+    }
+    pub fn with_containment(mut self, containment: LightContainment) -> Self {
+        self.containment = containment;
+        self
+    }
+    pub fn get_shapes(&self) -> &[CasterShape] {
+        &self.shapes
+    }
+    /// Swaps out the shapes, keeping the opacity, tint and containment
+    pub fn set_shapes<I: IntoIterator<Item = CasterShape>>(&mut self, shapes: I) {
+        self.shapes = shapes.into_iter().collect();
+    }
+    pub fn get_containment(&self) -> LightContainment {
+        self.containment
+    }
+    pub fn set_containment(&mut self, containment: LightContainment) {
+        self.containment = containment;
+    }
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self
+    }
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+    pub fn get_opacity(&self) -> f32 {
+        self.opacity
+    }
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+    }
+    pub fn get_tint(&self) -> Color {
+        self.tint
+    }
+    pub fn set_tint(&mut self, tint: Color) {
+        self.tint = tint;
+    }
+    /// How much of each color channel gets through, or `None` if this casts a solid shadow
+    pub(super) fn get_transmittance(&self) -> Option<Vec3> {
+        if self.opacity >= 1.0 {
+            return None;
+        }
+        let tint = self.tint.to_linear();
+        Some(Vec3::new(tint.red, tint.green, tint.blue) * (1.0 - self.opacity))
+    }
+    pub fn get_tshapes(&self, pos: Pos) -> Vec<CasterShape> {
+        self.shapes
+            .iter()
+            .map(|shape| shape.translated(pos.as_vec2()))
+            .collect()
+    }
+}
+
+/// Put this on an entity with a `StaticTx` to stop its hitboxes from casting shadows
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct NoStaticTxShadow;
+
+/// Optional per-light settings for how shadows fall on this light
+#[derive(Component, Clone, Debug, Default, Reflect)]
+pub struct LightShadows {
+    /// How big the light source is. 0.0 gives hard shadows, anything bigger softens their edges (penumbra)
+    pub source_size: f32,
+    /// How far (in pixels) light reaches into blockers before the shadow starts, so the faces of walls get lit
+    pub penetration: f32,
+    /// What casters containing this light do to it. Overrides the casters' own setting.
+    /// `None` leaves it up to each caster, with `StaticTx` hitboxes always ignored.
+    pub containment: Option<LightContainment>,
+}
+impl LightShadows {
+    pub fn soft(source_size: f32) -> Self {
+        Self {
+            source_size,
+            ..default()
+        }
+    }
+    pub fn with_source_size(mut self, source_size: f32) -> Self {
+        self.source_size = source_size;
+        self
+    }
+    pub fn with_penetration(mut self, penetration: f32) -> Self {
+        self.penetration = penetration;
+        self
+    }
+    pub fn with_containment(mut self, containment: LightContainment) -> Self {
+        self.containment = Some(containment);
+        self
+    }
+}
+
+/// Global settings for what casts shadows
+#[derive(Resource, Clone, Debug, Reflect)]
+pub struct ShadowSettings {
+    /// Should entities with a `StaticTx` cast shadows (unless marked with `NoStaticTxShadow`)?
+    /// NOTE: `StaticTx` doesn't expose the kind of each hitbox, so this is all or nothing.
+    ///       For finer control, turn this off and use `ShadowCaster`.
+    pub static_tx_casts: bool,
+}
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            static_tx_casts: true,
+        }
+    }
+}
+
+pub(super) fn register_light_caster(app: &mut App) {
+    app.insert_resource(ShadowSettings::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concave_polygons_contain_only_their_inside() {
+        // An L, with the notch in the top right
+        let shape = CasterShape::Polygon(vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(4.0, 0.0),
+            Vec2::new(4.0, 2.0),
+            Vec2::new(2.0, 2.0),
+            Vec2::new(2.0, 4.0),
+            Vec2::new(0.0, 4.0),
+        ]);
+        assert!(shape.contains(Vec2::new(1.0, 3.0)));
+        assert!(shape.contains(Vec2::new(3.0, 1.0)));
+        assert!(!shape.contains(Vec2::new(3.0, 3.0)));
+        // Right on the inner corner
+        assert!(shape.contains(Vec2::new(2.0, 2.0)));
+    }
+}
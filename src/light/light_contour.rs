@@ -0,0 +1,197 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_2delight_physics::prelude::HBox;
+
+use super::light_visibility::SolidLine;
+
+/// How close two coordinates need to be to count as the same
+const CONTOUR_EPSILON: f32 = 0.01;
+
+/// Which side of a box an edge is on. Edges on opposite sides never merge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum EdgeSide {
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+impl EdgeSide {
+    fn is_horizontal(&self) -> bool {
+        matches!(self, Self::Bottom | Self::Top)
+    }
+    fn outward(&self) -> Vec2 {
+        match self {
+            Self::Left => Vec2::NEG_X,
+            Self::Right => Vec2::X,
+            Self::Bottom => Vec2::NEG_Y,
+            Self::Top => Vec2::Y,
+        }
+    }
+}
+
+/// Edges along the same line are grouped by side and (rounded) coordinate so they can be merged
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct EdgeLine {
+    side: EdgeSide,
+    /// The fixed coordinate (y for horizontal edges, x for vertical), in hundredths
+    at: i64,
+}
+
+fn strictly_inside(hbox: &HBox, point: Vec2) -> bool {
+    hbox.min_x() < point.x
+        && point.x < hbox.max_x()
+        && hbox.min_y() < point.y
+        && point.y < hbox.max_y()
+}
+
+/// Turns a bunch of (possibly touching or overlapping) hboxes into the lines around their outside.
+/// Edges shared between neighbouring boxes disappear, and edges that continue across boxes become one line.
+/// If `clip` (min, max) is given, only the parts of the outline inside that half-open rect are returned.
+pub(super) fn merge_hbox_edges(hboxes: &[&HBox], clip: Option<(Vec2, Vec2)>) -> Vec<SolidLine> {
+    let mut intervals: HashMap<EdgeLine, (f32, Vec<(f32, f32)>)> = HashMap::default();
+    for hbox in hboxes {
+        for side in [
+            EdgeSide::Left,
+            EdgeSide::Right,
+            EdgeSide::Bottom,
+            EdgeSide::Top,
+        ] {
+            let (at, start, end) = match side {
+                EdgeSide::Left => (hbox.min_x(), hbox.min_y(), hbox.max_y()),
+                EdgeSide::Right => (hbox.max_x(), hbox.min_y(), hbox.max_y()),
+                EdgeSide::Bottom => (hbox.min_y(), hbox.min_x(), hbox.max_x()),
+                EdgeSide::Top => (hbox.max_y(), hbox.min_x(), hbox.max_x()),
+            };
+            let to_point = |along: f32| {
+                if side.is_horizontal() {
+                    Vec2::new(along, at)
+                } else {
+                    Vec2::new(at, along)
+                }
+            };
+
+            // Split the edge wherever another box starts or stops along it
+            let mut splits = vec![start, end];
+            for other in hboxes {
+                let (other_min, other_max) = if side.is_horizontal() {
+                    (other.min_x(), other.max_x())
+                } else {
+                    (other.min_y(), other.max_y())
+                };
+                splits.extend(
+                    [other_min, other_max]
+                        .into_iter()
+                        .filter(|split| start < *split && *split < end),
+                );
+            }
+            splits.sort_by(|a, b| a.total_cmp(b));
+            splits.dedup_by(|a, b| (*a - *b).abs() < CONTOUR_EPSILON);
+
+            // Only keep the pieces with nothing solid just outside them
+            for piece in splits.windows(2) {
+                let mid = to_point((piece[0] + piece[1]) / 2.0);
+                let outside = mid + side.outward() * CONTOUR_EPSILON;
+                if hboxes.iter().any(|other| strictly_inside(other, outside)) {
+                    continue;
+                }
+                let line = EdgeLine {
+                    side,
+                    at: (at * 100.0).round() as i64,
+                };
+                let entry = intervals.entry(line).or_insert((at, vec![]));
+                entry.1.push((piece[0], piece[1]));
+            }
+        }
+    }
+
+    let mut lines = vec![];
+    for (line, (at, mut pieces)) in intervals {
+        let (mut lo, mut hi) = (f32::NEG_INFINITY, f32::INFINITY);
+        if let Some((min, max)) = clip {
+            let (fixed_min, fixed_max, along_min, along_max) = if line.side.is_horizontal() {
+                (min.y, max.y, min.x, max.x)
+            } else {
+                (min.x, max.x, min.y, max.y)
+            };
+            if at < fixed_min || at >= fixed_max {
+                continue;
+            }
+            (lo, hi) = (along_min, along_max);
+        }
+        pieces.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut merged: Vec<(f32, f32)> = vec![];
+        for (start, end) in pieces {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 + CONTOUR_EPSILON => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        for (start, end) in merged {
+            let (start, end) = (start.max(lo), end.min(hi));
+            if end - start < CONTOUR_EPSILON {
+                continue;
+            }
+            let (a, b) = if line.side.is_horizontal() {
+                (Vec2::new(start, at), Vec2::new(end, at))
+            } else {
+                (Vec2::new(at, start), Vec2::new(at, end))
+            };
+            lines.push(SolidLine { a, b });
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has_line(lines: &[SolidLine], a: Vec2, b: Vec2) -> bool {
+        lines.iter().any(|line| {
+            (line.a.distance(a) < CONTOUR_EPSILON && line.b.distance(b) < CONTOUR_EPSILON)
+                || (line.a.distance(b) < CONTOUR_EPSILON && line.b.distance(a) < CONTOUR_EPSILON)
+        })
+    }
+
+    #[test]
+    fn adjacent_boxes_merge_into_one_contour() {
+        let left = HBox::new(4, 4);
+        let right = HBox::new(4, 4).with_offset(4.0, 0.0);
+        let lines = merge_hbox_edges(&[&left, &right], None);
+        assert_eq!(lines.len(), 4);
+        assert!(has_line(&lines, Vec2::new(-2.0, 2.0), Vec2::new(6.0, 2.0)));
+        assert!(has_line(
+            &lines,
+            Vec2::new(-2.0, -2.0),
+            Vec2::new(6.0, -2.0)
+        ));
+        assert!(has_line(
+            &lines,
+            Vec2::new(-2.0, -2.0),
+            Vec2::new(-2.0, 2.0)
+        ));
+        assert!(has_line(&lines, Vec2::new(6.0, -2.0), Vec2::new(6.0, 2.0)));
+    }
+
+    #[test]
+    fn boxes_inside_others_add_nothing() {
+        let outer = HBox::new(8, 8);
+        let inner = HBox::new(2, 2).with_offset(1.0, 1.0);
+        let lines = merge_hbox_edges(&[&outer, &inner], None);
+        assert_eq!(lines.len(), 4);
+        assert!(has_line(&lines, Vec2::new(-4.0, 4.0), Vec2::new(4.0, 4.0)));
+    }
+
+    #[test]
+    fn clip_trims_the_outline() {
+        let hbox = HBox::new(8, 8);
+        let lines = merge_hbox_edges(
+            &[&hbox],
+            Some((Vec2::new(0.0, -10.0), Vec2::new(10.0, 10.0))),
+        );
+        // The left edge is outside the clip, and the top and bottom are cut in half
+        assert_eq!(lines.len(), 3);
+        assert!(has_line(&lines, Vec2::new(0.0, 4.0), Vec2::new(4.0, 4.0)));
+        assert!(has_line(&lines, Vec2::new(0.0, -4.0), Vec2::new(4.0, -4.0)));
+        assert!(has_line(&lines, Vec2::new(4.0, -4.0), Vec2::new(4.0, 4.0)));
+    }
+}
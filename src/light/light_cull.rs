@@ -0,0 +1,40 @@
+use bevy::prelude::*;
+use bevy_2delight_physics::prelude::Pos;
+
+use crate::{camera::DynamicCamera, plugin::LayersRes, LightInteractionSet};
+
+use super::{light_alloc::manage_light_claims, light_source::LightSource};
+
+/// Works out which lights reach the visible area around the `DynamicCamera` (plus the light margin).
+/// Offscreen lights give their claim back in `manage_light_claims`, so they aren't drawn or shadowed.
+/// They still light things for probes and sensors, but are only traced once one of those asks about them.
+fn cull_offscreen_lights(
+    mut light_q: Query<(&Pos, &mut LightSource)>,
+    dynamic_camera_q: Query<&Pos, With<DynamicCamera>>,
+    res: Res<LayersRes>,
+) {
+    let Ok(focus) = dynamic_camera_q.get_single() else {
+        return;
+    };
+    let half_view = res.screen_size.as_vec2() / 2.0 + Vec2::splat(res.light_margin as f32);
+    for (pos, mut source) in &mut light_q {
+        let Some(radius) = source.radius else {
+            // Off lights aren't drawn anyway, leave them be
+            continue;
+        };
+        let diff = (pos.as_vec2() - focus.as_vec2()).abs();
+        let onscreen = diff.x <= half_view.x + radius && diff.y <= half_view.y + radius;
+        if source.onscreen != onscreen {
+            source.onscreen = onscreen;
+        }
+    }
+}
+
+pub(super) fn register_light_cull(app: &mut App) {
+    app.add_systems(
+        Update,
+        cull_offscreen_lights
+            .in_set(LightInteractionSet)
+            .before(manage_light_claims),
+    );
+}
@@ -5,12 +5,25 @@ var light_texture: texture_2d<f32>;
 @group(2) @binding(2)
 var light_splr: sampler;
 
+@group(2) @binding(3)
+var<uniform> tint: vec4<f32>;
+
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
     let original = textureSample(light_texture, light_splr, in.uv);
-    let avg = (original.x + original.y + original.z) / 3.0;
+    // Each channel is squared on its own, so light that went through a tinted caster keeps its color
+    // without being dimmed by the channels that got filtered out
+    let squared = original.xyz * original.xyz;
+    // Lights sharing the atlas each carry their own tint in the vertex colors
+#ifdef VERTEX_COLORS
+    let total_tint = tint * in.color;
+#else
+    let total_tint = tint;
+#endif
     return vec4<f32>(
-        1.0, 1.0, 1.0,
-        avg * avg 
+        squared.x * total_tint.x,
+        squared.y * total_tint.y,
+        squared.z * total_tint.z,
+        max(squared.x, max(squared.y, squared.z))
     );
 }
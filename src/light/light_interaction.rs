@@ -1,185 +1,582 @@
+use std::sync::OnceLock;
+
 use bevy::{
     asset::RenderAssetUsages,
     prelude::*,
-    render::{
-        mesh::{Indices, PrimitiveTopology},
-        primitives::Aabb,
-        view::RenderLayers,
-    },
-    sprite::ColorMaterial,
+    render::mesh::{Indices, PrimitiveTopology},
+    utils::HashMap,
 };
-use bevy_2delight_anims::prelude::AnimMan;
 use bevy_2delight_physics::{
-    prelude::{HBox, Pos, StaticTx},
+    prelude::{HBox, Pos},
     PhysicsSet,
 };
 
-use crate::{
-    plugin::LayersRes,
-    prelude::{LightAnim, LightMan},
+use crate::LightInteractionSet;
+
+use super::{
+    light_broadphase::{BlockerGrid, NearbyCaster},
+    light_caster::{CasterShape, LightContainment, LightShadows},
+    light_contour::merge_hbox_edges,
+    light_mat::ShadowMat,
+    light_probe::{LightFilter, LightTrace, LitRegion, LitRegions},
+    light_proc::LightCone,
+    light_source::LightSource,
+    light_visibility::{SolidLine, VisibilityPolygon},
 };
 
-pub(super) const BLACK_MAT_HAND: Handle<ColorMaterial> = Handle::weak_from_u128(12398129387129837);
-const MAX_LIGHT_EXTENT: f32 = 500.0;
+pub(super) const SHADOW_MAT_HAND: Handle<ShadowMat> = Handle::weak_from_u128(12398129387129837);
+/// How many slices each penumbra gradient is made of
+const PENUMBRA_STEPS: u32 = 4;
+/// How much further one ray has to go than its neighbor for us to consider it a shadow edge
+const PENUMBRA_MIN_JUMP: f32 = 1.0;
+/// The widest (in radians) each slice of the mask outside a cone can be
+const CONE_MASK_MAX_SLICE: f32 = std::f32::consts::FRAC_PI_8;
 
-#[derive(Component)]
-struct TemporaryLightMesh;
+/// Does the box from `min` to `max` overlap the square of half size `extent` around `source`?
+fn bounds_within_extent((min, max): (Vec2, Vec2), source: Vec2, extent: f32) -> bool {
+    min.x <= source.x + extent
+        && max.x >= source.x - extent
+        && min.y <= source.y + extent
+        && max.y >= source.y - extent
+}
 
-struct SolidLine {
-    a: Vec2,
-    b: Vec2,
+/// Does this line overlap the square of half size `extent` around `source`?
+fn line_within_extent(line: &SolidLine, source: Vec2, extent: f32) -> bool {
+    bounds_within_extent((line.a.min(line.b), line.a.max(line.b)), source, extent)
 }
 
-fn hbox_to_solid_lines(hbox: &HBox) -> [SolidLine; 4] {
-    let offset = hbox.get_offset();
-    let size = hbox.get_size().as_vec2();
-    let left = Vec2::X * size.x / 2.0;
-    let up = Vec2::Y * size.y / 2.0;
-    [
-        SolidLine {
-            a: offset - left - up,
-            b: offset - left + up,
-        },
-        SolidLine {
-            a: offset - left + up,
-            b: offset + left + up,
-        },
-        SolidLine {
-            a: offset + left + up,
-            b: offset + left - up,
-        },
-        SolidLine {
-            a: offset + left - up,
-            b: offset - left - up,
-        },
-    ]
+/// Is the source inside (or on the edge of) this box? What happens then is up to `LightContainment`
+fn hbox_contains_source(hbox: &HBox, source: Vec2) -> bool {
+    hbox.manhattan_distance_to_point(source) <= 0.1
 }
 
-fn hbox_to_blocked_mesh(source: Vec2, hbox: &HBox) -> (Mesh, Aabb) {
-    let get_blocked = |p: Vec2| -> Vec2 { p + (p - source).normalize_or_zero() * MAX_LIGHT_EXTENT };
+/// The area behind a translucent shape (as seen from `source`), out past `extent`.
+/// Starts at the line between the shape's two outermost corners, so it also covers the far half of the shape.
+fn filter_quad(shape: &CasterShape, source: Vec2, extent: f32) -> Option<[Vec2; 4]> {
+    let (min, max) = shape.bounds();
+    let facing = ((min + max) / 2.0 - source).normalize_or_zero();
+    let angle_of = |point: Vec2| {
+        let offset = point - source;
+        facing.perp_dot(offset).atan2(facing.dot(offset))
+    };
+    let corners = shape
+        .to_solid_lines()
+        .into_iter()
+        .flat_map(|line| [line.a, line.b])
+        .collect::<Vec<_>>();
+    let left = corners
+        .iter()
+        .copied()
+        .max_by(|a, b| angle_of(*a).total_cmp(&angle_of(*b)))?;
+    let right = corners
+        .iter()
+        .copied()
+        .min_by(|a, b| angle_of(*a).total_cmp(&angle_of(*b)))?;
+    let project = |point: Vec2| source + (point - source).normalize_or_zero() * extent * 4.0;
+    Some([right, left, project(left), project(right)])
+}
 
-    let mut points = Vec::<Vec2>::new();
-    let mut tris = Vec::<u32>::new();
+/// Geometry of everything blocking a single light, turned into one mesh
+#[derive(Default)]
+pub(super) struct ShadowGeometry {
+    points: Vec<Vec2>,
+    /// How much of each channel of the light gets through at each point, 0.0 being fully blocked
+    colors: Vec<Vec4>,
+    tris: Vec<u32>,
+}
+impl ShadowGeometry {
+    fn add_colored_tri(&mut self, corners: [(Vec2, Vec4); 3]) {
+        let first_ix = self.points.len() as u32;
+        self.tris.extend([first_ix, first_ix + 1, first_ix + 2]);
+        for (point, color) in corners {
+            self.points.push(point);
+            self.colors.push(color);
+        }
+    }
 
-    for line in hbox_to_solid_lines(hbox) {
-        let first_ix = points.len() as u32;
-        tris.extend([first_ix, first_ix + 1, first_ix + 2]);
-        tris.extend([first_ix + 2, first_ix + 3, first_ix]);
-        points.extend([line.a, get_blocked(line.a), get_blocked(line.b), line.b]);
+    /// A tri of plain shadow, with `alpha` being how much of the light is blocked at each corner
+    pub(super) fn add_tri(&mut self, corners: [(Vec2, f32); 3]) {
+        self.add_colored_tri(corners.map(|(point, alpha)| (point, Vec4::splat(1.0 - alpha))));
     }
 
-    let min_x = points
-        .iter()
-        .map(|p| p.x)
-        .min_by(|a, b| a.total_cmp(b))
-        .unwrap();
-    let min_y = points
-        .iter()
-        .map(|p| p.y)
-        .min_by(|a, b| a.total_cmp(b))
-        .unwrap();
-    let max_x = points
-        .iter()
-        .map(|p| p.x)
-        .max_by(|a, b| a.total_cmp(b))
-        .unwrap();
-    let max_y = points
-        .iter()
-        .map(|p| p.y)
-        .max_by(|a, b| a.total_cmp(b))
-        .unwrap();
-    let get_frac = |x: f32, min: f32, max: f32| (x - min) / (max - min);
-
-    let mut inserted_positions = vec![];
-    let mut inserted_uvs = vec![];
-    let mut inserted_normals = vec![];
-
-    for point in points.into_iter() {
-        inserted_positions.push([point.x, point.y, 0.0]);
-        inserted_uvs.push([
-            get_frac(point.x, min_x, max_x),
-            get_frac(point.y, min_y, max_y),
-        ]);
-        inserted_normals.push([0.0, 0.0, 1.0]);
+    /// Colors (and dims) all the light passing through a translucent caster
+    pub(super) fn add_filter(&mut self, filter: &LightFilter) {
+        let color = filter.transmittance.extend(1.0);
+        let [a, b, c, d] = filter.corners;
+        self.add_colored_tri([(a, color), (b, color), (c, color)]);
+        self.add_colored_tri([(c, color), (d, color), (a, color)]);
+    }
+
+    /// Fills in everything the light can't reach
+    fn add_visibility(&mut self, visibility: &VisibilityPolygon) {
+        for (left, right) in visibility.wedges() {
+            if left.visible.distance_squared(left.bound) < 0.01
+                && right.visible.distance_squared(right.bound) < 0.01
+            {
+                // Nothing blocked in this wedge
+                continue;
+            }
+            self.add_tri([(left.visible, 1.0), (left.bound, 1.0), (right.bound, 1.0)]);
+            self.add_tri([
+                (right.bound, 1.0),
+                (right.visible, 1.0),
+                (left.visible, 1.0),
+            ]);
+        }
+    }
+
+    /// Softens the edge of every shadow by fanning out a gradient from each blocker corner
+    /// towards the lit side. The bigger the source (and the closer the corner) the wider the fan.
+    fn add_penumbras(&mut self, visibility: &VisibilityPolygon, source: Vec2, source_size: f32) {
+        let length = visibility.extent * 2.0;
+        for (left, right) in visibility.wedges() {
+            let left_dist = left.visible.distance(source);
+            let right_dist = right.visible.distance(source);
+            if (left_dist - right_dist).abs() < PENUMBRA_MIN_JUMP {
+                // Not a shadow edge
+                continue;
+            }
+            // The nearer point is a blocker corner, and light spills past it towards the farther ray
+            let (corner, spin) = if left_dist < right_dist {
+                (left.visible, 1.0)
+            } else {
+                (right.visible, -1.0)
+            };
+            let corner_dist = left_dist.min(right_dist).max(1.0);
+            let spread = spin * 2.0 * (source_size / 2.0 / corner_dist).atan();
+            let base = (corner - source).normalize_or_zero() * length;
+            let at_step = |step: u32| -> (Vec2, f32) {
+                let frac = step as f32 / PENUMBRA_STEPS as f32;
+                let point = corner + Vec2::from_angle(spread * frac).rotate(base);
+                (point, 1.0 - frac)
+            };
+            for step in 0..PENUMBRA_STEPS {
+                let (inner, inner_alpha) = at_step(step);
+                let (outer, outer_alpha) = at_step(step + 1);
+                let mid_alpha = (inner_alpha + outer_alpha) / 2.0;
+                self.add_tri([
+                    (corner, mid_alpha),
+                    (inner, inner_alpha),
+                    (outer, outer_alpha),
+                ]);
+            }
+        }
+    }
+
+    /// Blacks out everything outside a spot light's cone, so neither the light nor its shadows spill past it
+    fn add_cone_mask(&mut self, source: Vec2, extent: f32, cone: LightCone) {
+        // Far enough out that the slices cover the corners of the extent
+        let length = extent * 2.0;
+        let start = cone.direction + cone.width / 2.0;
+        let masked = std::f32::consts::TAU - cone.width.clamp(0.0, std::f32::consts::TAU);
+        let slices = (masked / CONE_MASK_MAX_SLICE).ceil() as u32;
+        let at_slice = |ix: u32| {
+            let angle = start + masked * ix as f32 / slices as f32;
+            source + Vec2::from_angle(angle) * length
+        };
+        for ix in 0..slices {
+            self.add_tri([(source, 1.0), (at_slice(ix), 1.0), (at_slice(ix + 1), 1.0)]);
+        }
+    }
+
+    /// Cuts everything down to the box from `min` to `max`, so none of it spills onto neighbouring lights
+    fn clipped(self, min: Vec2, max: Vec2) -> Self {
+        // Each edge of the box, as (normal pointing inside, distance of the edge along that normal)
+        let edges = [
+            (Vec2::X, min.x),
+            (Vec2::NEG_X, -max.x),
+            (Vec2::Y, min.y),
+            (Vec2::NEG_Y, -max.y),
+        ];
+        let mut clipped = Self::default();
+        for tri in self.tris.chunks_exact(3) {
+            let mut poly = tri
+                .iter()
+                .map(|ix| (self.points[*ix as usize], self.colors[*ix as usize]))
+                .collect::<Vec<_>>();
+            for (normal, at) in edges {
+                let inside = |point: Vec2| normal.dot(point) - at;
+                let mut kept = vec![];
+                for (ix, (point, color)) in poly.iter().enumerate() {
+                    let (next_point, next_color) = poly[(ix + 1) % poly.len()];
+                    let (here, next) = (inside(*point), inside(next_point));
+                    if here >= 0.0 {
+                        kept.push((*point, *color));
+                    }
+                    if (here >= 0.0) != (next >= 0.0) {
+                        let frac = here / (here - next);
+                        kept.push((point.lerp(next_point, frac), color.lerp(next_color, frac)));
+                    }
+                }
+                poly = kept;
+                if poly.len() < 3 {
+                    break;
+                }
+            }
+            for ix in 1..poly.len().saturating_sub(1) {
+                clipped.add_colored_tri([poly[0], poly[ix], poly[ix + 1]]);
+            }
+        }
+        clipped
     }
 
-    (
+    pub(super) fn into_mesh(mut self) -> Mesh {
+        if self.tris.is_empty() {
+            // Nothing is blocked, but the mesh can't be empty, so draw a degenerate triangle
+            self.add_tri([(Vec2::ZERO, 0.0); 3]);
+        }
+        let positions = self
+            .points
+            .iter()
+            .map(|p| [p.x, p.y, 0.0])
+            .collect::<Vec<_>>();
+        let colors = self
+            .colors
+            .iter()
+            .map(|color| color.to_array())
+            .collect::<Vec<_>>();
+        let uvs = vec![[0.0, 0.0]; self.points.len()];
+        let normals = vec![[0.0, 0.0, 1.0]; self.points.len()];
+
         Mesh::new(
             PrimitiveTopology::TriangleList,
             RenderAssetUsages::default(),
         )
-        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, inserted_positions)
-        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, inserted_uvs)
-        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, inserted_normals)
-        .with_inserted_indices(Indices::U32(tris)),
-        Aabb::enclosing([Vec3::new(min_x, min_y, 0.0), Vec3::new(max_x, max_y, 0.0)]).unwrap(),
-    )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, colors)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_indices(Indices::U32(self.tris))
+    }
 }
 
-fn block_lights<Anim: LightAnim>(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    layers_res: Res<LayersRes>,
-    // NOTE: Because we reuse meshes (which might be stupid) we have to take in a dummy exclusive
-    //       ref to the `TemporaryLightMesh` so different variants of this system never run in parallel
-    old_q: Query<(Entity, &Mesh2d, &mut TemporaryLightMesh)>,
-    source_q: Query<(&Pos, &LightMan<Anim>, &AnimMan<Anim>)>,
-    blocker_q: Query<(&Pos, &StaticTx)>,
-) {
-    let mut old_iter = old_q.iter();
-    let mut make_or_reuse_mesh = |rl: RenderLayers, mesh: Mesh, aabb: Aabb| {
-        if let Some((eid, emesh, _)) = old_iter.next() {
-            let Some(mr) = meshes.get_mut(emesh.id()) else {
-                return;
-            };
-            *mr = mesh;
-            commands.entity(eid).insert((aabb, rl));
-        } else {
-            commands
-                .spawn((
-                    Name::new("temporary_mesh"),
-                    Mesh2d(meshes.add(mesh).into()),
-                    MeshMaterial2d(BLACK_MAT_HAND.clone()),
-                    Transform::from_translation(Vec3::Z * 100.0),
-                    Visibility::Inherited,
-                    rl,
-                    TemporaryLightMesh,
-                ))
-                .set_parent(layers_res.root_eid());
+/// What a light's shadows were last computed with. If none of it changes, neither do the shadows.
+#[derive(Clone, PartialEq)]
+pub(super) struct ShadowCacheKey {
+    pub(super) source: Vec2,
+    pub(super) radius: f32,
+    source_size: f32,
+    penetration: f32,
+    /// The mesh the shadows go in, and the size of the tile they're cut down to
+    shadow_mesh: Option<(AssetId<Mesh>, u32)>,
+    /// The entities the light is attached to (parent, grandparent...), whose casters it ignores
+    ancestors: Vec<Entity>,
+    containment: Option<LightContainment>,
+    cone: Option<LightCone>,
+}
+pub(super) struct CachedShadow {
+    key: ShadowCacheKey,
+    /// The blocker grid tick when the shadows were computed
+    tick: u64,
+}
+
+/// Works out what a light reaches, going around (or through) everything near it
+pub(super) fn trace_light(
+    light_eid: Entity,
+    key: &ShadowCacheKey,
+    grid: &BlockerGrid,
+) -> LightTrace {
+    let (source_v2, light_radius) = (key.source, key.radius);
+    let mut lines = vec![];
+    let mut occluded = false;
+    let mut filters = vec![];
+    let whole_extent = [
+        source_v2 + Vec2::new(-light_radius, -light_radius),
+        source_v2 + Vec2::new(light_radius, -light_radius),
+        source_v2 + Vec2::new(light_radius, light_radius),
+        source_v2 + Vec2::new(-light_radius, light_radius),
+    ];
+
+    let static_hboxes = grid.static_hboxes_near(source_v2, light_radius);
+    let (containing, others): (Vec<_>, Vec<_>) = static_hboxes
+        .into_iter()
+        .partition(|hbox| hbox_contains_source(hbox, source_v2));
+    if containing.is_empty() {
+        lines.extend(grid.static_lines_near(source_v2, light_radius).copied());
+    } else {
+        // The cached outline includes the box the source is in, so outline everything else on the spot
+        lines.extend(merge_hbox_edges(&others, None));
+        match key.containment.unwrap_or_default() {
+            LightContainment::IgnoreCaster => {}
+            LightContainment::FullyOccluded => occluded = true,
+            // Seen from inside, the box's own walls block everything past them
+            LightContainment::InteriorOnly => lines.extend(
+                containing
+                    .iter()
+                    .flat_map(|hbox| CasterShape::HBox((*hbox).clone()).to_solid_lines()),
+            ),
         }
-    };
+    }
+    lines.retain(|line| line_within_extent(line, source_v2, light_radius));
+    for caster in grid.caster_shapes_near(source_v2, light_radius) {
+        let NearbyCaster {
+            eid: caster_eid,
+            shape,
+            transmittance,
+            ..
+        } = caster;
+        if caster_eid == light_eid || key.ancestors.contains(&caster_eid) {
+            // Bodies don't shadow the lights they carry
+            continue;
+        }
+        if !bounds_within_extent(shape.bounds(), source_v2, light_radius) {
+            continue;
+        }
+        if shape.contains(source_v2) {
+            match (key.containment.unwrap_or(caster.containment), transmittance) {
+                (LightContainment::IgnoreCaster, _) => {}
+                // Light still gets out of a glass jar, it's just filtered everywhere
+                (_, Some(transmittance)) => filters.push(LightFilter {
+                    corners: whole_extent,
+                    transmittance,
+                }),
+                (LightContainment::FullyOccluded, None) => occluded = true,
+                (LightContainment::InteriorOnly, None) => lines.extend(shape.to_solid_lines()),
+            }
+            continue;
+        }
+        match transmittance {
+            // Filters multiply on top of each other (and the shadows), so they don't block rays
+            Some(transmittance) => {
+                filters.extend(filter_quad(shape, source_v2, light_radius).map(|corners| {
+                    LightFilter {
+                        corners,
+                        transmittance,
+                    }
+                }))
+            }
+            None => lines.extend(shape.to_solid_lines()),
+        }
+    }
+    let mut visibility = VisibilityPolygon::new(source_v2, light_radius, &lines);
+    if key.penetration > 0.0 {
+        visibility.penetrate(key.penetration);
+    }
+    if occluded {
+        visibility.occlude();
+    }
+    LightTrace {
+        visibility,
+        filters,
+    }
+}
 
-    for (source_pos, light, anim) in &source_q {
-        let Some(light_radius) = anim.get_state().light_radius() else {
-            // Returning none here means the light is intentionally off/not active for this variant
+pub(super) fn block_lights(
+    mut meshes: ResMut<Assets<Mesh>>,
+    source_q: Query<(Entity, &Pos, &LightSource, Option<&LightShadows>)>,
+    parent_q: Query<&Parent>,
+    grid: Res<BlockerGrid>,
+    mut lit_regions: ResMut<LitRegions>,
+    mut cache: Local<HashMap<Entity, CachedShadow>>,
+) {
+    let mut fresh_cache = HashMap::default();
+    for (eid, source_pos, source, shadows) in &source_q {
+        // Returning none here means the light is intentionally off/not active, so nothing is blocked
+        let Some(light_radius) = source.radius else {
+            lit_regions.map.remove(&eid);
             continue;
         };
         let source_v2 = source_pos.as_vec2();
-        for (blocker_pos, stx) in &blocker_q {
-            for blocker_hbox in stx.get_thboxes(*blocker_pos) {
-                if blocker_hbox.manhattan_distance_to_point(source_v2) > light_radius {
-                    continue;
-                }
-                if blocker_hbox.manhattan_distance_to_point(source_v2) <= 0.1 {
-                    // If a source is inside a box we want to ignore that box, useful for passup
-                    continue;
+        let key = ShadowCacheKey {
+            source: source_v2,
+            radius: light_radius,
+            source_size: shadows.map_or(0.0, |shadows| shadows.source_size),
+            penetration: shadows.map_or(0.0, |shadows| shadows.penetration),
+            shadow_mesh: source
+                .claim
+                .as_ref()
+                .map(|claim| (claim.shadow_mesh_hand.id(), claim.tile.size)),
+            ancestors: parent_q.iter_ancestors(eid).collect(),
+            containment: shadows.and_then(|shadows| shadows.containment),
+            cone: source.falloff.cone,
+        };
+        if let Some(cached) = cache.remove(&eid) {
+            if cached.key == key
+                && grid.last_changed_near(source_v2, light_radius) <= cached.tick
+                && lit_regions.map.contains_key(&eid)
+            {
+                // Nothing moved, so the shadows (and lit region) from last time are still good
+                if let Some(region) = lit_regions.map.get_mut(&eid) {
+                    region.falloff = source.falloff;
                 }
-                let (mesh, aabb) = hbox_to_blocked_mesh(source_v2, &blocker_hbox);
-                make_or_reuse_mesh(
-                    RenderLayers::from_layers(&[light.claim.rl_usize]),
-                    mesh,
-                    aabb,
-                );
+                fresh_cache.insert(eid, cached);
+                continue;
             }
         }
-    }
 
-    drop(make_or_reuse_mesh);
-    while let Some((eid, _, _)) = old_iter.next() {
-        commands.entity(eid).despawn_recursive();
+        // Lights without a claim (like offscreen ones) still light things up for probes and sensors,
+        // but nobody sees their shadows, so they aren't traced until a `LightProbe` asks about them
+        let trace = OnceLock::new();
+        if let Some((mesh, tile_size)) = key
+            .shadow_mesh
+            .and_then(|(id, tile_size)| Some((meshes.get_mut(id)?, tile_size)))
+        {
+            let LightTrace {
+                visibility,
+                filters,
+            } = trace.get_or_init(|| trace_light(eid, &key, &grid));
+            let mut geometry = ShadowGeometry::default();
+            geometry.add_visibility(visibility);
+            if key.source_size > 0.0 {
+                geometry.add_penumbras(visibility, source_v2, key.source_size);
+            }
+            for filter in filters.iter() {
+                geometry.add_filter(filter);
+            }
+            if let Some(cone) = key.cone {
+                geometry.add_cone_mask(source_v2, light_radius, cone);
+            }
+            // The tile is centered on the rounded position, see `place_light_claims`
+            let half_tile = Vec2::splat(tile_size as f32 / 2.0);
+            let center = source_v2.round();
+            *mesh = geometry
+                .clipped(center - half_tile, center + half_tile)
+                .into_mesh();
+        }
+        lit_regions.map.insert(
+            eid,
+            LitRegion {
+                light_eid: eid,
+                falloff: source.falloff,
+                key: key.clone(),
+                trace,
+            },
+        );
+        fresh_cache.insert(
+            eid,
+            CachedShadow {
+                key,
+                tick: grid.get_tick(),
+            },
+        );
     }
+    // Anything left over belongs to lights that are gone
+    for eid in cache.keys() {
+        lit_regions.map.remove(eid);
+    }
+    *cache = fresh_cache;
+}
+
+pub(super) fn register_light_interaction(app: &mut App) {
+    app.configure_sets(Update, LightInteractionSet.after(PhysicsSet));
+    app.add_systems(Update, block_lights.in_set(LightInteractionSet));
 }
 
-pub(super) fn register_light_interaction<Anim: LightAnim>(app: &mut App) {
-    app.add_systems(Update, block_lights::<Anim>.after(PhysicsSet));
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::light::{
+        light_broadphase::update_blocker_grid,
+        light_caster::{ShadowCaster, ShadowSettings},
+    };
+
+    fn lit_source(radius: f32) -> LightSource {
+        let mut source = LightSource::new();
+        source.radius = Some(radius);
+        source
+    }
+
+    fn is_lit(world: &World, light: Entity, point: Vec2) -> bool {
+        world
+            .resource::<LitRegions>()
+            .map
+            .get(&light)
+            .is_some_and(|region| {
+                let grid = world.resource::<BlockerGrid>();
+                region.trace(grid).visibility.contains(point)
+            })
+    }
+
+    #[test]
+    fn clipped_shadows_stay_in_their_tile() {
+        let mut geometry = ShadowGeometry::default();
+        geometry.add_tri([
+            (Vec2::new(0.0, 0.0), 1.0),
+            (Vec2::new(20.0, 0.0), 1.0),
+            (Vec2::new(0.0, 20.0), 1.0),
+        ]);
+        let clipped = geometry.clipped(Vec2::splat(-8.0), Vec2::splat(8.0));
+        assert!(!clipped.tris.is_empty());
+        for point in clipped.points.iter() {
+            assert!(point.x >= -8.001 && point.x <= 8.001);
+            assert!(point.y >= -8.001 && point.y <= 8.001);
+        }
+        // The square from the origin to (8, 8) is all covered, which is 64 square pixels
+        let area = clipped
+            .tris
+            .chunks_exact(3)
+            .map(|tri| {
+                let [a, b, c] = [0, 1, 2].map(|ix| clipped.points[tri[ix] as usize]);
+                (b - a).perp_dot(c - a).abs() / 2.0
+            })
+            .sum::<f32>();
+        assert!((area - 64.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn casters_never_block_lights_they_carry() {
+        let mut world = World::new();
+        world.init_resource::<BlockerGrid>();
+        world.init_resource::<ShadowSettings>();
+        world.init_resource::<LitRegions>();
+        world.init_resource::<Assets<Mesh>>();
+
+        // A body holding a lantern out in its hand, and a lamp hanging right next to the lantern
+        let body = world
+            .spawn((Pos::new(0.0, 0.0), ShadowCaster::single(HBox::new(8, 8))))
+            .id();
+        let hand = world.spawn(Pos::new(-6.0, 0.0)).set_parent(body).id();
+        let lantern = world
+            .spawn((Pos::new(-10.0, 0.0), lit_source(32.0)))
+            .set_parent(hand)
+            .id();
+        let lamp = world.spawn((Pos::new(-10.0, 0.0), lit_source(32.0))).id();
+
+        world.run_system_once(update_blocker_grid).unwrap();
+        world.run_system_once(block_lights).unwrap();
+
+        let behind_body = Vec2::new(10.0, 0.0);
+        assert!(is_lit(&world, lantern, behind_body));
+        assert!(!is_lit(&world, lamp, behind_body));
+    }
+
+    #[test]
+    fn lights_nobody_sees_are_traced_on_demand() {
+        let mut world = World::new();
+        world.init_resource::<BlockerGrid>();
+        world.init_resource::<ShadowSettings>();
+        world.init_resource::<LitRegions>();
+        world.init_resource::<Assets<Mesh>>();
+
+        // Without a claim (like when it's offscreen) nothing draws this light's shadows
+        let wall = world
+            .spawn((Pos::new(10.0, 0.0), ShadowCaster::single(HBox::new(4, 16))))
+            .id();
+        let light = world.spawn((Pos::new(0.0, 0.0), lit_source(32.0))).id();
+
+        // Registered once, so its cache carries over between runs
+        let block_lights = world.register_system(block_lights);
+        world.run_system_once(update_blocker_grid).unwrap();
+        world.run_system(block_lights).unwrap();
+        let untraced = |world: &World| {
+            world.resource::<LitRegions>().map[&light]
+                .trace
+                .get()
+                .is_none()
+        };
+        assert!(untraced(&world));
+
+        // Asking about it traces it, and the trace sticks around until something nearby changes
+        assert!(!is_lit(&world, light, Vec2::new(20.0, 0.0)));
+        assert!(is_lit(&world, light, Vec2::new(-20.0, 0.0)));
+        world.run_system(block_lights).unwrap();
+        assert!(!untraced(&world));
+        world.entity_mut(wall).insert(Pos::new(10.0, 20.0));
+        world.run_system_once(update_blocker_grid).unwrap();
+        world.run_system(block_lights).unwrap();
+        assert!(untraced(&world));
+        assert!(is_lit(&world, light, Vec2::new(20.0, 0.0)));
+    }
 }
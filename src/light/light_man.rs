@@ -1,13 +1,23 @@
+use std::any::TypeId;
+
 use bevy::{prelude::*, render::view::RenderLayers};
-use bevy_2delight_anims::{prelude::AnimDefnPlugin, AnimSet};
+use bevy_2delight_anims::{
+    prelude::{AnimDefnPlugin, AnimMan},
+    AnimSet,
+};
 
-use crate::LightAnimSet;
+use crate::{LightAnimSet, DUMMY_LAYER_USIZE};
 
-use super::{light_alloc::LightClaim, light_interaction::register_light_interaction};
+use super::{
+    light_proc::{LightCone, LightFacing},
+    light_source::{LightFalloff, LightSource},
+};
 
 /// A trait that will allow lighting systems to use this anim as light source
 pub trait LightAnim: bevy_2delight_anims::prelude::AnimStateMachine {
-    /// How far does this light extend? Helps prune light interaction calcs
+    /// How far does this light extend? Helps prune light interaction calcs.
+    /// NOTE: The light's sprite can be bigger than this, its tile in the light atlas grows to fit it.
+    ///       But only the radius is shadowed, so anything drawn past it shines through blockers.
     fn light_radius(&self) -> Option<f32>;
 }
 
@@ -25,55 +35,60 @@ impl<Anim: LightAnim> LightStateUpdate<Anim> {
     }
 }
 
-/// A light source manager, implemented as a thin wrapper around AnimMan
-/// TODO: Figure out if this makes sense, get_state seems fucky... idk... also implement flips
+/// A light source manager, implemented as a thin wrapper around AnimMan.
+/// Add a `LightFacing` to flip and turn the light (and its cone) along with its owner.
+/// TODO: Figure out if this makes sense, get_state seems fucky... idk...
 #[derive(Component)]
 #[component(on_add = on_add_light_man::<Anim>)]
 #[component(on_remove = on_remove_light_man::<Anim>)]
 pub struct LightMan<Anim: LightAnim> {
     pub(crate) state_update: Option<LightStateUpdate<Anim>>,
-    pub(super) claim: LightClaim,
+    /// Multiplied on top of the color of the light sprite
+    tint: Color,
+    /// If set, only this cone of the anim is lit (and casts shadows), making it a spot light
+    cone: Option<LightCone>,
 }
-/// Responsible for getting a light claim from the world and creating the underlying anim
+/// Responsible for creating the light source and the underlying anim.
+/// The anim starts out on the dummy render layer, and is moved onto the atlas once the light gets a claim.
 fn on_add_light_man<Anim: LightAnim>(
     mut world: bevy::ecs::world::DeferredWorld,
     eid: Entity,
     _: bevy::ecs::component::ComponentId,
 ) {
-    // Get da claim
-    let claim = LightClaim::alloc(&mut world);
-    let mut myself = world.get_mut::<LightMan<Anim>>(eid).unwrap();
-    myself.claim = claim.clone();
-    let start_state = myself
+    let start_state = world
+        .get::<LightMan<Anim>>(eid)
+        .unwrap()
         .state_update
-        .as_mut()
+        .as_ref()
         .map(|inner| inner.get_state())
         .cloned();
 
     // Make da anim
-    world.commands().entity(eid).insert(
-        bevy_2delight_anims::prelude::AnimMan::new(start_state.unwrap_or_default())
-            .with_render_layers(RenderLayers::from_layers(&[claim.rl_usize])),
-    );
+    let mut source = LightSource::new();
+    source.own_anim = Some(TypeId::of::<Anim>());
+    world.commands().entity(eid).insert((
+        source,
+        AnimMan::new(start_state.unwrap_or_default())
+            .with_render_layers(RenderLayers::from_layers(&[DUMMY_LAYER_USIZE])),
+    ));
 }
-/// Responsible for releasing the light claim and removing the underlying anim
+/// Responsible for releasing the light source (and with it the claim) and removing the underlying anim
 fn on_remove_light_man<Anim: LightAnim>(
     mut world: bevy::ecs::world::DeferredWorld,
     eid: Entity,
     _: bevy::ecs::component::ComponentId,
 ) {
-    let claim = world.get::<LightMan<Anim>>(eid).unwrap().claim.clone();
-    claim.free(&mut world);
     world
         .commands()
         .entity(eid)
-        .remove::<bevy_2delight_anims::prelude::AnimMan<Anim>>();
+        .remove::<(LightSource, AnimMan<Anim>)>();
 }
 impl<Anim: LightAnim> LightMan<Anim> {
     pub fn new(state: Anim) -> Self {
         Self {
             state_update: Some(LightStateUpdate::Reset(state)),
-            claim: default(),
+            tint: Color::WHITE,
+            cone: None,
         }
     }
     pub fn with_state(mut self, state: Anim) -> Self {
@@ -86,14 +101,37 @@ impl<Anim: LightAnim> LightMan<Anim> {
     pub fn reset_state(&mut self, state: Anim) {
         self.state_update = Some(LightStateUpdate::Reset(state));
     }
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+    pub fn set_tint(&mut self, tint: Color) {
+        self.tint = tint;
+    }
+    pub fn get_tint(&self) -> Color {
+        self.tint
+    }
+    pub fn with_cone(mut self, cone: LightCone) -> Self {
+        self.cone = Some(cone);
+        self
+    }
+    pub fn set_cone(&mut self, cone: Option<LightCone>) {
+        self.cone = cone;
+    }
+    pub fn get_cone(&self) -> Option<LightCone> {
+        self.cone
+    }
 }
 
+/// A `LightMan` along with the anim it drives, if that's still around
+type DrivenLight<Anim> = (
+    Entity,
+    &'static mut LightMan<Anim>,
+    Option<&'static mut AnimMan<Anim>>,
+);
+
 fn drive_light_anims<Anim: LightAnim>(
-    mut light_q: Query<(
-        Entity,
-        &mut LightMan<Anim>,
-        Option<&mut bevy_2delight_anims::prelude::AnimMan<Anim>>,
-    )>,
+    mut light_q: Query<DrivenLight<Anim>>,
     mut commands: Commands,
 ) {
     for (eid, mut light, mut anim) in &mut light_q {
@@ -117,6 +155,43 @@ fn drive_light_anims<Anim: LightAnim>(
     }
 }
 
+/// Everything a `LightMan` keeps in sync with its anim and light source
+type SyncedLight<Anim> = (
+    &'static LightMan<Anim>,
+    &'static mut AnimMan<Anim>,
+    &'static mut LightSource,
+    Option<&'static LightFacing>,
+);
+
+fn sync_light_sources<Anim: LightAnim>(mut light_q: Query<SyncedLight<Anim>>) {
+    for (light, mut anim, mut source, facing) in &mut light_q {
+        LightSource::sync(
+            source.reborrow(),
+            anim.get_state().light_radius(),
+            light.tint,
+        );
+        LightSource::sync_falloff(
+            source.reborrow(),
+            LightFalloff {
+                cone: light.cone.map(|cone| cone.faced(facing)),
+                ..default()
+            },
+        );
+        let body_angle = facing.map_or(0.0, |facing| facing.body_angle());
+        if source.body_angle != body_angle {
+            source.body_angle = body_angle;
+        }
+        if let Some(facing) = facing {
+            if anim.get_flip_x() != facing.flip_x {
+                anim.set_flip_x(facing.flip_x);
+            }
+            if anim.get_flip_y() != facing.flip_y {
+                anim.set_flip_y(facing.flip_y);
+            }
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct LightDefnPlugin<Anim: LightAnim> {
     _pd: std::marker::PhantomData<Anim>,
@@ -124,12 +199,17 @@ pub struct LightDefnPlugin<Anim: LightAnim> {
 impl<Anim: LightAnim> Plugin for LightDefnPlugin<Anim> {
     fn build(&self, app: &mut App) {
         app.add_plugins(AnimDefnPlugin::<Anim>::default());
-        register_light_interaction::<Anim>(app);
         app.add_systems(
             PostUpdate,
             drive_light_anims::<Anim>
                 .in_set(LightAnimSet)
                 .before(AnimSet),
         );
+        app.add_systems(
+            Update,
+            sync_light_sources::<Anim>
+                .after(super::light_proc::FollowOwnerSet)
+                .before(crate::LightInteractionSet),
+        );
     }
 }
@@ -1,11 +1,19 @@
 use bevy::{
     prelude::*,
-    render::render_resource::{AsBindGroup, ShaderRef},
-    sprite::Material2d,
+    render::{
+        mesh::MeshVertexBufferLayoutRef,
+        render_resource::{
+            AsBindGroup, BlendComponent, BlendFactor, BlendOperation, BlendState,
+            RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+        },
+    },
+    sprite::{Material2d, Material2dKey},
 };
 
 use crate::utils::color_as_vec4;
 
+use super::light_proc::LightCone;
+
 /// The mat that does the multiplying
 #[derive(AsBindGroup, Debug, Clone, Asset, Reflect, PartialEq)]
 pub(crate) struct LightApplyMat {
@@ -17,6 +25,9 @@ pub(crate) struct LightApplyMat {
     light: Handle<Image>,
     #[uniform(5)]
     base: Vec4,
+    /// How much of the light target lines up with the screen, the rest is the light margin
+    #[uniform(6)]
+    light_scale: Vec2,
 }
 impl Material2d for LightApplyMat {
     fn fragment_shader() -> ShaderRef {
@@ -27,28 +38,74 @@ impl Material2d for LightApplyMat {
     }
 }
 impl LightApplyMat {
-    pub fn new(image: Handle<Image>, light: Handle<Image>, base: Color) -> Self {
+    pub fn new(image: Handle<Image>, light: Handle<Image>, base: Color, light_scale: Vec2) -> Self {
         Self {
             image,
             light,
             base: color_as_vec4(base),
+            light_scale,
+        }
+    }
+    pub(crate) fn set_base(&mut self, base: Color) {
+        self.base = color_as_vec4(base);
+    }
+}
+
+/// Draws shadows onto a light by multiplying whatever is already there by the mesh's vertex colors.
+/// Black blocks everything, white blocks nothing, and anything else filters the light (stained glass, foliage).
+#[derive(AsBindGroup, Debug, Clone, Default, Asset, Reflect, PartialEq)]
+pub(crate) struct ShadowMat {}
+impl Material2d for ShadowMat {
+    fn fragment_shader() -> ShaderRef {
+        "embedded://bevy_2delight_layers/light/light_shadow_mat.wgsl".into()
+    }
+    fn alpha_mode(&self) -> bevy::sprite::AlphaMode2d {
+        bevy::sprite::AlphaMode2d::Blend
+    }
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: Material2dKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // Shadows (and filters) stack by multiplying, so the order they're drawn in doesn't matter
+        let multiply = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::Src,
+            operation: BlendOperation::Add,
+        };
+        if let Some(target) = descriptor
+            .fragment
+            .as_mut()
+            .and_then(|fragment| fragment.targets.first_mut())
+            .and_then(|target| target.as_mut())
+        {
+            target.blend = Some(BlendState {
+                color: multiply,
+                alpha: multiply,
+            });
         }
+        Ok(())
     }
 }
 
-/// After we draw the lights to a render layer and then cut out shapes with black meshes,
+/// After we draw the lights into the atlas (or a sun's target) and then cut out shapes with shadow meshes,
 /// the image we are left with does not have proper opacity.
 /// This shader accomplishes two things:
-/// 1. Makes it so that all lights have rgba(1.0, 1.0, 1.0, a) so they blend correctly
+/// 1. Keeps the hue of the light (times the light's tint, and whatever colored casters it went through),
+///    weighted by how bright it is. The tint is the uniform times the vertex colors, if the mesh has any,
+///    so one mesh can copy many differently tinted lights out of the atlas.
 /// 2. Makes it so that black maps to clear
+///
 /// The point is that we have many lights, and we can just throw the output of all of their "cutouts"
-/// into the same layer, they'll blend correctly into a final image, and that is our lighting
+/// into the same layer, they'll add up into a final (colored) image, and that is our lighting
 /// source of truth.
 #[derive(AsBindGroup, Debug, Clone, Asset, Reflect, PartialEq)]
 pub(crate) struct LightCutoutMat {
     #[texture(1)]
     #[sampler(2)]
     light: Handle<Image>,
+    #[uniform(3)]
+    tint: Vec4,
 }
 impl Material2d for LightCutoutMat {
     fn fragment_shader() -> ShaderRef {
@@ -57,9 +114,82 @@ impl Material2d for LightCutoutMat {
     fn alpha_mode(&self) -> bevy::sprite::AlphaMode2d {
         bevy::sprite::AlphaMode2d::Blend
     }
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayoutRef,
+        _key: Material2dKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // Lights should add up in the light layer, not cover each other
+        let additive = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        if let Some(target) = descriptor
+            .fragment
+            .as_mut()
+            .and_then(|fragment| fragment.targets.first_mut())
+            .and_then(|target| target.as_mut())
+        {
+            target.blend = Some(BlendState {
+                color: additive,
+                alpha: additive,
+            });
+        }
+        Ok(())
+    }
 }
 impl LightCutoutMat {
     pub fn new(light: Handle<Image>) -> Self {
-        Self { light }
+        Self {
+            light,
+            tint: color_as_vec4(Color::WHITE),
+        }
+    }
+    pub(crate) fn set_tint(&mut self, tint: Color) {
+        self.tint = color_as_vec4(tint);
+    }
+}
+
+/// Draws a `ProcLight` into its claim.
+/// NOTE: Outputs the square root of the brightness in grayscale, so that after the cutout shader
+///       squares it, the light layer gets exactly `brightness * tint` (the tint being the light color).
+#[derive(AsBindGroup, Debug, Clone, Asset, Reflect, PartialEq)]
+pub(crate) struct ProcLightMat {
+    /// (intensity, falloff, _, _)
+    #[uniform(1)]
+    shape: Vec4,
+    /// (direction.x, direction.y, half width, 1.0 if there is a cone else 0.0)
+    #[uniform(2)]
+    cone: Vec4,
+}
+impl Default for ProcLightMat {
+    fn default() -> Self {
+        Self {
+            shape: Vec4::new(1.0, 1.0, 0.0, 0.0),
+            cone: Vec4::ZERO,
+        }
+    }
+}
+impl Material2d for ProcLightMat {
+    fn fragment_shader() -> ShaderRef {
+        "embedded://bevy_2delight_layers/light/light_proc_mat.wgsl".into()
+    }
+    fn alpha_mode(&self) -> bevy::sprite::AlphaMode2d {
+        bevy::sprite::AlphaMode2d::Blend
+    }
+}
+impl ProcLightMat {
+    pub(crate) fn set_shape(&mut self, intensity: f32, falloff: f32, cone: Option<LightCone>) {
+        self.shape = Vec4::new(intensity, falloff, 0.0, 0.0);
+        self.cone = match cone {
+            Some(cone) => Vec4::new(
+                cone.direction.cos(),
+                cone.direction.sin(),
+                cone.width / 2.0,
+                1.0,
+            ),
+            None => Vec4::ZERO,
+        };
     }
 }
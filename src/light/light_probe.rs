@@ -0,0 +1,131 @@
+use std::sync::OnceLock;
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+use bevy_2delight_physics::prelude::HBox;
+
+use super::{
+    light_broadphase::BlockerGrid,
+    light_caster::CasterShape,
+    light_interaction::{trace_light, ShadowCacheKey},
+    light_source::LightFalloff,
+    light_visibility::VisibilityPolygon,
+};
+
+/// The area behind a translucent caster, where only some of a light gets through
+pub(super) struct LightFilter {
+    pub(super) corners: [Vec2; 4],
+    /// How much of each color channel gets through
+    pub(super) transmittance: Vec3,
+}
+impl LightFilter {
+    fn contains(&self, point: Vec2) -> bool {
+        CasterShape::Polygon(self.corners.to_vec()).contains(point)
+    }
+}
+
+/// What a light reaches once it's been traced past everything blocking (or filtering) it
+pub(super) struct LightTrace {
+    pub(super) visibility: VisibilityPolygon,
+    pub(super) filters: Vec<LightFilter>,
+}
+
+/// Everything a single light reaches, as of the last time shadows were computed
+pub(super) struct LitRegion {
+    pub(super) light_eid: Entity,
+    pub(super) falloff: LightFalloff,
+    /// What the light gets traced with
+    pub(super) key: ShadowCacheKey,
+    /// Lights whose shadows nobody sees (like offscreen ones) are only traced once something asks
+    pub(super) trace: OnceLock<LightTrace>,
+}
+impl LitRegion {
+    pub(super) fn trace(&self, grid: &BlockerGrid) -> &LightTrace {
+        self.trace
+            .get_or_init(|| trace_light(self.light_eid, &self.key, grid))
+    }
+    fn brightness_at(&self, point: Vec2, grid: &BlockerGrid) -> f32 {
+        // Checked first, so lights that can't reach the point never need tracing
+        let brightness = self
+            .falloff
+            .brightness(point - self.key.source, self.key.radius);
+        if brightness <= 0.0 {
+            return 0.0;
+        }
+        let trace = self.trace(grid);
+        if !trace.visibility.contains(point) {
+            return 0.0;
+        }
+        let filtered = trace
+            .filters
+            .iter()
+            .filter(|filter| filter.contains(point))
+            .map(|filter| filter.transmittance.element_sum() / 3.0)
+            .product::<f32>();
+        brightness * filtered
+    }
+}
+
+/// The points we sample when asking about a whole hbox
+fn hbox_probe_points(hbox: &HBox) -> [Vec2; 5] {
+    [
+        hbox.get_offset(),
+        Vec2::new(hbox.min_x(), hbox.min_y()),
+        Vec2::new(hbox.min_x(), hbox.max_y()),
+        Vec2::new(hbox.max_x(), hbox.min_y()),
+        Vec2::new(hbox.max_x(), hbox.max_y()),
+    ]
+}
+
+/// The lit regions of every light that is currently on, keyed by light entity.
+/// Kept up to date in `LightInteractionSet`. Use `LightProbe` to read it.
+#[derive(Resource, Default)]
+pub struct LitRegions {
+    pub(super) map: HashMap<Entity, LitRegion>,
+}
+
+/// Answers "how lit is this?" on the CPU, using the same radii and blockers that draw the shadows.
+/// Systems that run after `LightInteractionSet` see this frame's lighting, otherwise last frame's.
+/// NOTE: Ignores light color, ambience and soft shadow edges. Translucent casters dim light by how much
+///       gets through them on average, ignoring their tint.
+#[derive(SystemParam)]
+pub struct LightProbe<'w> {
+    regions: Res<'w, LitRegions>,
+    grid: Res<'w, BlockerGrid>,
+}
+impl LightProbe<'_> {
+    /// Every light reaching this point, along with how bright it is there
+    pub fn lights_at(&self, point: Vec2) -> impl Iterator<Item = (Entity, f32)> + '_ {
+        self.regions.map.iter().filter_map(move |(eid, region)| {
+            let brightness = region.brightness_at(point, &self.grid);
+            (brightness > 0.0).then_some((*eid, brightness))
+        })
+    }
+    /// How much light reaches this point, summed over all lights (so it may go over 1.0)
+    pub fn intensity_at(&self, point: Vec2) -> f32 {
+        self.lights_at(point)
+            .map(|(_, brightness)| brightness)
+            .sum()
+    }
+    /// How much light reaches the brightest of this hbox's center and corners
+    pub fn intensity_in(&self, hbox: &HBox) -> f32 {
+        hbox_probe_points(hbox)
+            .into_iter()
+            .map(|point| self.intensity_at(point))
+            .fold(0.0, f32::max)
+    }
+    /// Every light reaching any of this hbox's center and corners, along with the brightest it gets
+    pub fn lights_in<'a>(&'a self, hbox: &HBox) -> impl Iterator<Item = (Entity, f32)> + 'a {
+        let points = hbox_probe_points(hbox);
+        self.regions.map.iter().filter_map(move |(eid, region)| {
+            let brightness = points
+                .iter()
+                .map(|point| region.brightness_at(*point, &self.grid))
+                .fold(0.0, f32::max);
+            (brightness > 0.0).then_some((*eid, brightness))
+        })
+    }
+}
+
+pub(super) fn register_light_probe(app: &mut App) {
+    app.insert_resource(LitRegions::default());
+}
@@ -0,0 +1,456 @@
+use std::any::TypeId;
+
+use bevy::{ecs::world::EntityRef, prelude::*, render::view::RenderLayers};
+use bevy_2delight_anims::prelude::{AnimMan, AnimStateMachine};
+
+use crate::{LightInteractionSet, DUMMY_LAYER_USIZE};
+
+use super::{
+    light_mat::ProcLightMat,
+    light_source::{LightFalloff, LightSource},
+};
+
+/// Restricts a `ProcLight` to a cone
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct LightCone {
+    /// Which way the cone points, in radians counter-clockwise from +x
+    pub direction: f32,
+    /// The full angle of the cone, in radians
+    pub width: f32,
+}
+impl LightCone {
+    /// The cone turned to match `facing`: rotated first, then mirrored
+    pub(crate) fn faced(&self, facing: Option<&LightFacing>) -> Self {
+        let Some(facing) = facing else {
+            return *self;
+        };
+        let mut dir = Vec2::from_angle(self.direction + facing.rotation);
+        if facing.flip_x {
+            dir.x = -dir.x;
+        }
+        if facing.flip_y {
+            dir.y = -dir.y;
+        }
+        Self {
+            direction: dir.to_angle(),
+            width: self.width,
+        }
+    }
+}
+
+/// Which way the entity carrying a light is facing. Cones on the same entity (from a `ProcLight` or
+/// `LightMan`) turn to match: rotated counter-clockwise by `rotation`, then mirrored by the flips.
+/// `LightMan` anims are rotated and flipped along with them.
+/// With `follow_owner`, the flips are copied every frame from the owner: this entity or its closest ancestor
+/// with a `Sprite` or an `AnimMan`. Owners with an `AnimMan` are only followed if that anim is registered
+/// with a `LightFacingPlugin`, and a `LightMan`'s own anim never counts, since the facing flips it.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub struct LightFacing {
+    pub rotation: f32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub follow_owner: bool,
+}
+impl LightFacing {
+    pub fn new(rotation: f32) -> Self {
+        Self {
+            rotation,
+            ..default()
+        }
+    }
+    /// Faces wherever the owner (see above) does
+    pub fn following_owner() -> Self {
+        Self {
+            follow_owner: true,
+            ..default()
+        }
+    }
+    pub fn with_flip_x(mut self, flip_x: bool) -> Self {
+        self.flip_x = flip_x;
+        self
+    }
+    pub fn with_flip_y(mut self, flip_y: bool) -> Self {
+        self.flip_y = flip_y;
+        self
+    }
+    /// How far to turn an anim so that, once its sprite is flipped, it matches `LightCone::faced`.
+    /// Mirroring once turns the rotation around, mirroring both ways doesn't.
+    pub(crate) fn body_angle(&self) -> f32 {
+        if self.flip_x != self.flip_y {
+            -self.rotation
+        } else {
+            self.rotation
+        }
+    }
+    /// Copies the owner's flips, only triggering change detection if they actually changed
+    fn follow(mut this: Mut<Self>, flip_x: bool, flip_y: bool) {
+        if this.flip_x != flip_x || this.flip_y != flip_y {
+            this.flip_x = flip_x;
+            this.flip_y = flip_y;
+        }
+    }
+}
+
+/// Where facings are copied from their owners. Cones and anims are turned after this.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct FollowOwnerSet;
+
+/// A light source that is drawn by a shader instead of an anim.
+/// Useful for the (many) lights that are just a circle (or cone) fading out.
+#[derive(Component, Clone, Debug)]
+#[component(on_add = on_add_proc_light)]
+#[component(on_remove = on_remove_proc_light)]
+#[require(bevy_2delight_physics::prelude::Pos)]
+pub struct ProcLight {
+    radius: f32,
+    /// How bright the center of the light is, 0.0 - 1.0
+    intensity: f32,
+    /// Brightness at distance `d` is `intensity * (1 - d / radius) ^ falloff`
+    falloff: f32,
+    color: Color,
+    cone: Option<LightCone>,
+    on: bool,
+    /// INTERNAL: The entity with the mesh that actually draws the light
+    body_eid: Entity,
+    /// INTERNAL: The material on that mesh
+    mat_hand: Handle<ProcLightMat>,
+}
+impl ProcLight {
+    pub fn radial(radius: f32) -> Self {
+        Self {
+            radius,
+            intensity: 1.0,
+            falloff: 1.0,
+            color: Color::WHITE,
+            cone: None,
+            on: true,
+            body_eid: Entity::PLACEHOLDER,
+            mat_hand: default(),
+        }
+    }
+    pub fn cone(radius: f32, direction: f32, width: f32) -> Self {
+        Self::radial(radius).with_cone(LightCone { direction, width })
+    }
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+    pub fn with_falloff(mut self, falloff: f32) -> Self {
+        self.falloff = falloff;
+        self
+    }
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+    pub fn with_cone(mut self, cone: LightCone) -> Self {
+        self.cone = Some(cone);
+        self
+    }
+    pub fn with_on(mut self, on: bool) -> Self {
+        self.on = on;
+        self
+    }
+    pub fn get_radius(&self) -> f32 {
+        self.radius
+    }
+    pub fn set_radius(&mut self, radius: f32) {
+        self.radius = radius;
+    }
+    pub fn get_intensity(&self) -> f32 {
+        self.intensity
+    }
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+    pub fn get_falloff(&self) -> f32 {
+        self.falloff
+    }
+    pub fn set_falloff(&mut self, falloff: f32) {
+        self.falloff = falloff;
+    }
+    pub fn get_color(&self) -> Color {
+        self.color
+    }
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+    pub fn get_cone(&self) -> Option<LightCone> {
+        self.cone
+    }
+    pub fn set_cone(&mut self, cone: Option<LightCone>) {
+        self.cone = cone;
+    }
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+    pub fn set_on(&mut self, on: bool) {
+        self.on = on;
+    }
+}
+
+/// Responsible for creating the light source and spawning the mesh that draws the light.
+/// The mesh starts out on the dummy render layer, and is moved onto the atlas once the light gets a claim.
+fn on_add_proc_light(
+    mut world: bevy::ecs::world::DeferredWorld,
+    eid: Entity,
+    _: bevy::ecs::component::ComponentId,
+) {
+    let mesh = world
+        .resource_mut::<Assets<Mesh>>()
+        .add(Mesh::from(Rectangle::new(1.0, 1.0)));
+    let mat_hand = world
+        .resource_mut::<Assets<ProcLightMat>>()
+        .add(ProcLightMat::default());
+    let body_eid = world
+        .commands()
+        .spawn((
+            Name::new("ProcLightBody"),
+            Mesh2d(mesh),
+            MeshMaterial2d(mat_hand.clone()),
+            Transform::default(),
+            Visibility::Inherited,
+            RenderLayers::from_layers(&[DUMMY_LAYER_USIZE]),
+        ))
+        .set_parent(eid)
+        .id();
+
+    let mut myself = world.get_mut::<ProcLight>(eid).unwrap();
+    myself.body_eid = body_eid;
+    myself.mat_hand = mat_hand;
+    world.commands().entity(eid).insert(LightSource::new());
+}
+/// Responsible for releasing the light source (and with it the claim) and removing the body
+fn on_remove_proc_light(
+    mut world: bevy::ecs::world::DeferredWorld,
+    eid: Entity,
+    _: bevy::ecs::component::ComponentId,
+) {
+    let body_eid = world.get::<ProcLight>(eid).unwrap().body_eid;
+    if let Some(comms) = world.commands().get_entity(body_eid) {
+        comms.despawn_recursive();
+    }
+    world.commands().entity(eid).remove::<LightSource>();
+}
+
+/// Reads the flips of an owner, if it has the right kind of `AnimMan`
+type AnimFlips = fn(&EntityRef) -> Option<(bool, bool)>;
+
+/// Every anim registered with a `LightFacingPlugin`, along with how to read its flips
+#[derive(Resource, Default)]
+struct FacingAnims {
+    anims: Vec<(TypeId, AnimFlips)>,
+}
+
+fn anim_flips<A: AnimStateMachine>(owner: &EntityRef) -> Option<(bool, bool)> {
+    let anim = owner.get::<AnimMan<A>>()?;
+    Some((anim.get_flip_x(), anim.get_flip_y()))
+}
+
+/// A facing, along with the entity it is on
+type FollowingFacing = (Entity, &'static mut LightFacing);
+
+/// Copies the flips of each following facing's owner, the first of the entity itself and then its ancestors
+/// that has a `Sprite` or a registered `AnimMan`
+fn follow_owners(
+    mut queries: ParamSet<(Query<FollowingFacing>, Query<EntityRef>)>,
+    parent_q: Query<&Parent>,
+    facing_anims: Res<FacingAnims>,
+) {
+    let following = queries
+        .p0()
+        .iter()
+        .filter(|(_, facing)| facing.follow_owner)
+        .map(|(eid, _)| eid)
+        .collect::<Vec<_>>();
+    let owner_q = queries.p1();
+    let flips = following
+        .into_iter()
+        .filter_map(|eid| {
+            let own_anim = owner_q
+                .get(eid)
+                .ok()
+                .and_then(|myself| myself.get::<LightSource>()?.own_anim);
+            let flips = std::iter::once(eid)
+                .chain(parent_q.iter_ancestors(eid))
+                .find_map(|owner_eid| {
+                    let owner = owner_q.get(owner_eid).ok()?;
+                    if let Some(sprite) = owner.get::<Sprite>() {
+                        return Some((sprite.flip_x, sprite.flip_y));
+                    }
+                    facing_anims
+                        .anims
+                        .iter()
+                        .filter(|(anim, _)| owner_eid != eid || own_anim != Some(*anim))
+                        .find_map(|(_, flips)| flips(&owner))
+                })?;
+            Some((eid, flips))
+        })
+        .collect::<Vec<_>>();
+    let mut facing_q = queries.p0();
+    for (eid, (flip_x, flip_y)) in flips {
+        if let Ok((_, facing)) = facing_q.get_mut(eid) {
+            LightFacing::follow(facing, flip_x, flip_y);
+        }
+    }
+}
+
+/// Lets `LightFacing::following_owner` follow owners animated with an `AnimMan<A>`
+#[derive(Default)]
+pub struct LightFacingPlugin<A: AnimStateMachine> {
+    _pd: std::marker::PhantomData<A>,
+}
+impl<A: AnimStateMachine> Plugin for LightFacingPlugin<A> {
+    fn build(&self, app: &mut App) {
+        app.world_mut()
+            .get_resource_or_insert_with(FacingAnims::default)
+            .anims
+            .push((TypeId::of::<A>(), anim_flips::<A>));
+    }
+}
+
+/// Proc lights whose light source (or body) might be out of date
+type ChangedProcLight = Or<(Changed<ProcLight>, Added<LightSource>, Changed<LightFacing>)>;
+
+fn update_proc_lights(
+    mut light_q: Query<(&ProcLight, &mut LightSource, Option<&LightFacing>), ChangedProcLight>,
+    mut body_q: Query<(&mut Transform, &mut Visibility)>,
+    mut mats: ResMut<Assets<ProcLightMat>>,
+) {
+    for (light, mut source, facing) in &mut light_q {
+        let cone = light.cone.map(|cone| cone.faced(facing));
+        LightSource::sync(
+            source.reborrow(),
+            if light.on { Some(light.radius) } else { None },
+            light.color,
+        );
+        LightSource::sync_falloff(
+            source,
+            LightFalloff {
+                intensity: light.intensity,
+                falloff: light.falloff,
+                cone,
+            },
+        );
+        if let Ok((mut tran, mut vis)) = body_q.get_mut(light.body_eid) {
+            tran.scale = (Vec2::ONE * light.radius * 2.0).extend(1.0);
+            *vis = if light.on {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+        }
+        if let Some(mat) = mats.get_mut(light.mat_hand.id()) {
+            mat.set_shape(light.intensity, light.falloff, cone);
+        }
+    }
+}
+
+pub(super) fn register_light_proc(app: &mut App) {
+    app.configure_sets(Update, FollowOwnerSet.before(LightInteractionSet));
+    app.init_resource::<FacingAnims>();
+    app.add_systems(Update, follow_owners.in_set(FollowOwnerSet));
+    app.add_systems(
+        Update,
+        update_proc_lights
+            .after(FollowOwnerSet)
+            .before(LightInteractionSet),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    /// Stands in for an `AnimMan`, which can't be spawned without the whole anim (and render) setup
+    #[derive(Component)]
+    struct FakeAnim {
+        flip_x: bool,
+    }
+    /// Some other anim a `LightMan` might be drawn with
+    struct OtherAnim;
+
+    fn fake_anim_flips(owner: &EntityRef) -> Option<(bool, bool)> {
+        owner.get::<FakeAnim>().map(|anim| (anim.flip_x, false))
+    }
+
+    fn world_with_fake_anim() -> World {
+        let mut world = World::new();
+        world.insert_resource(FacingAnims {
+            anims: vec![(TypeId::of::<FakeAnim>(), fake_anim_flips)],
+        });
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<ProcLightMat>>();
+        world
+    }
+
+    /// A `LightSource` like the one a `LightMan` drawn with `A` carries
+    fn light_man_source<A: 'static>() -> LightSource {
+        let mut source = LightSource::new();
+        source.own_anim = Some(TypeId::of::<A>());
+        source
+    }
+
+    fn is_flipped(world: &World, eid: Entity) -> bool {
+        world.get::<LightFacing>(eid).unwrap().flip_x
+    }
+
+    #[test]
+    fn closer_sprites_beat_farther_anims() {
+        let mut world = world_with_fake_anim();
+        // An animated player holding a torch sprite, which is flipped on its own
+        let player = world.spawn(FakeAnim { flip_x: false }).id();
+        let torch = world
+            .spawn(Sprite {
+                flip_x: true,
+                ..default()
+            })
+            .set_parent(player)
+            .id();
+        let light = world
+            .spawn(LightFacing::following_owner())
+            .set_parent(torch)
+            .id();
+
+        world.run_system_once(follow_owners).unwrap();
+        assert!(is_flipped(&world, light));
+    }
+
+    #[test]
+    fn anims_on_the_light_are_followed_unless_they_draw_it() {
+        let mut world = world_with_fake_anim();
+        let parent = world.spawn(FakeAnim { flip_x: false }).id();
+        let proc_light = world
+            .spawn((
+                ProcLight::radial(16.0),
+                FakeAnim { flip_x: true },
+                LightFacing::following_owner(),
+            ))
+            .set_parent(parent)
+            .id();
+        let other_light = world
+            .spawn((
+                light_man_source::<OtherAnim>(),
+                FakeAnim { flip_x: true },
+                LightFacing::following_owner(),
+            ))
+            .set_parent(parent)
+            .id();
+        // This anim draws the light, so the facing flips it instead and the parent is followed
+        let own_light = world
+            .spawn((
+                light_man_source::<FakeAnim>(),
+                FakeAnim { flip_x: true },
+                LightFacing::following_owner(),
+            ))
+            .set_parent(parent)
+            .id();
+
+        world.run_system_once(follow_owners).unwrap();
+        assert!(is_flipped(&world, proc_light));
+        assert!(is_flipped(&world, other_light));
+        assert!(!is_flipped(&world, own_light));
+    }
+}
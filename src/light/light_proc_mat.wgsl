@@ -0,0 +1,31 @@
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+// (intensity, falloff, _, _)
+@group(2) @binding(1)
+var<uniform> shape: vec4<f32>;
+// (direction.x, direction.y, half width, 1.0 if there is a cone else 0.0)
+@group(2) @binding(2)
+var<uniform> cone: vec4<f32>;
+
+// How many radians it takes the edge of a cone to fade out
+const CONE_SOFTNESS: f32 = 0.1;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    // uv y goes down, world y goes up
+    let p = vec2<f32>(in.uv.x - 0.5, 0.5 - in.uv.y) * 2.0;
+    let dist = length(p);
+    if (dist >= 1.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    }
+
+    var brightness = shape.x * pow(1.0 - dist, shape.y);
+    if (cone.w > 0.5 && dist > 0.0) {
+        let diff = acos(clamp(dot(p / dist, cone.xy), -1.0, 1.0));
+        brightness = brightness * (1.0 - smoothstep(cone.z - CONE_SOFTNESS, cone.z, diff));
+    }
+
+    // The cutout shader squares this, see `ProcLightMat`
+    let val = sqrt(clamp(brightness, 0.0, 1.0));
+    return vec4<f32>(val, val, val, 1.0);
+}
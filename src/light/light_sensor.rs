@@ -0,0 +1,103 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_2delight_physics::prelude::{HBox, Pos};
+
+use crate::LightInteractionSet;
+
+use super::light_probe::LightProbe;
+
+/// Put this on an entity to keep track of which lights reach it.
+/// The lights are listed in `LitBy`, and changes fire `LightEntered`/`LightExited`.
+#[derive(Component, Clone, Debug, Default)]
+#[require(Pos, LitBy)]
+pub struct LightSensor {
+    /// `None` means only the point at `Pos` is checked
+    hbox: Option<HBox>,
+}
+impl LightSensor {
+    pub fn point() -> Self {
+        Self { hbox: None }
+    }
+    pub fn hbox(hbox: HBox) -> Self {
+        Self { hbox: Some(hbox) }
+    }
+}
+
+/// The lights currently reaching a `LightSensor`, along with how bright each is there
+#[derive(Component, Clone, Debug, Default)]
+pub struct LitBy {
+    lights: HashMap<Entity, f32>,
+}
+impl LitBy {
+    pub fn is_lit(&self) -> bool {
+        !self.lights.is_empty()
+    }
+    pub fn contains(&self, light_eid: Entity) -> bool {
+        self.lights.contains_key(&light_eid)
+    }
+    pub fn get_brightness(&self, light_eid: Entity) -> Option<f32> {
+        self.lights.get(&light_eid).copied()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, f32)> + '_ {
+        self.lights
+            .iter()
+            .map(|(eid, brightness)| (*eid, *brightness))
+    }
+}
+
+/// Fired when a light starts reaching a `LightSensor`
+#[derive(Event, Clone, Debug)]
+pub struct LightEntered {
+    pub sensor_eid: Entity,
+    pub light_eid: Entity,
+}
+
+/// Fired when a light stops reaching a `LightSensor` (including when the light turns off or is removed)
+#[derive(Event, Clone, Debug)]
+pub struct LightExited {
+    pub sensor_eid: Entity,
+    pub light_eid: Entity,
+}
+
+fn update_light_sensors(
+    mut sensor_q: Query<(Entity, &Pos, &LightSensor, &mut LitBy)>,
+    probe: LightProbe,
+    mut entered_writer: EventWriter<LightEntered>,
+    mut exited_writer: EventWriter<LightExited>,
+) {
+    for (sensor_eid, pos, sensor, mut lit_by) in &mut sensor_q {
+        let lights: HashMap<Entity, f32> = match &sensor.hbox {
+            Some(hbox) => probe.lights_in(&hbox.translated(pos.x, pos.y)).collect(),
+            None => probe.lights_at(pos.as_vec2()).collect(),
+        };
+        for light_eid in lights.keys() {
+            if !lit_by.lights.contains_key(light_eid) {
+                entered_writer.send(LightEntered {
+                    sensor_eid,
+                    light_eid: *light_eid,
+                });
+            }
+        }
+        for light_eid in lit_by.lights.keys() {
+            if !lights.contains_key(light_eid) {
+                exited_writer.send(LightExited {
+                    sensor_eid,
+                    light_eid: *light_eid,
+                });
+            }
+        }
+        if lit_by.lights != lights {
+            lit_by.lights = lights;
+        }
+    }
+}
+
+pub(super) fn register_light_sensor(app: &mut App) {
+    app.add_event::<LightEntered>();
+    app.add_event::<LightExited>();
+    app.add_systems(
+        Update,
+        update_light_sensors
+            .in_set(LightInteractionSet)
+            .after(super::light_interaction::block_lights),
+    );
+}
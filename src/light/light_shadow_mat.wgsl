@@ -0,0 +1,11 @@
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The vertex color is how much of each channel gets through, and is multiplied onto the light
+#ifdef VERTEX_COLORS
+    return in.color;
+#else
+    return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+#endif
+}
@@ -0,0 +1,109 @@
+use std::any::TypeId;
+
+use bevy::prelude::*;
+
+use super::{light_alloc::LightClaim, light_proc::LightCone};
+
+/// How many radians it takes the edge of a cone to fade out. Matches `light_proc_mat.wgsl`
+const CONE_SOFTNESS: f32 = 0.1;
+
+/// How bright a light is at each point it reaches, ignoring blockers and color.
+/// Mirrors what the light actually draws so gameplay queries agree with what's on screen.
+/// Lights drawn by anims can be any shape, so those just fade out linearly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct LightFalloff {
+    pub(crate) intensity: f32,
+    pub(crate) falloff: f32,
+    pub(crate) cone: Option<LightCone>,
+}
+impl Default for LightFalloff {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            falloff: 1.0,
+            cone: None,
+        }
+    }
+}
+impl LightFalloff {
+    /// How bright is the light at `offset` from its center, for a light of the given radius?
+    pub(crate) fn brightness(&self, offset: Vec2, radius: f32) -> f32 {
+        let dist = offset.length() / radius;
+        if dist >= 1.0 {
+            return 0.0;
+        }
+        let mut brightness = self.intensity * (1.0 - dist).powf(self.falloff);
+        if let (Some(cone), true) = (self.cone, dist > 0.0) {
+            let diff = Vec2::from_angle(cone.direction).angle_to(offset).abs();
+            let half_width = cone.width / 2.0;
+            brightness *= 1.0 - smoothstep(half_width - CONE_SOFTNESS, half_width, diff);
+        }
+        brightness.clamp(0.0, 1.0)
+    }
+}
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// The non-generic half of every light. Each kind of light (`LightMan`, `ProcLight`) keeps one of these
+/// up to date, so the rest of the lighting code doesn't need to care where a light came from.
+#[derive(Component)]
+#[component(on_remove = on_remove_light_source)]
+pub(crate) struct LightSource {
+    /// How far does this light extend? `None` means the light is off
+    pub(crate) radius: Option<f32>,
+    /// Multiplied on top of whatever the light draws into its tile of the atlas
+    pub(crate) tint: Color,
+    /// How bright the light is across its radius
+    pub(crate) falloff: LightFalloff,
+    /// How far (in radians, counter-clockwise) whatever draws the light is turned in the atlas.
+    /// Only anims turn this way, proc lights turn their cone in the shader instead.
+    pub(crate) body_angle: f32,
+    /// The type of anim drawing this light, if it's a `LightMan`. That anim is flipped by the light's
+    /// `LightFacing`, so it's never what the facing follows.
+    pub(crate) own_anim: Option<TypeId>,
+    /// Does the light reach the visible area around the `DynamicCamera` (plus the light margin)?
+    /// Offscreen lights aren't drawn
+    pub(crate) onscreen: bool,
+    /// The rendering resources this light owns, if it's on and got any
+    pub(crate) claim: Option<LightClaim>,
+}
+impl LightSource {
+    pub(crate) fn new() -> Self {
+        Self {
+            radius: None,
+            tint: Color::WHITE,
+            falloff: default(),
+            body_angle: 0.0,
+            own_anim: None,
+            onscreen: true,
+            claim: None,
+        }
+    }
+    /// Updates the radius and tint, only triggering change detection if something actually changed
+    pub(crate) fn sync(mut this: Mut<Self>, radius: Option<f32>, tint: Color) {
+        if this.radius != radius {
+            this.radius = radius;
+        }
+        if this.tint != tint {
+            this.tint = tint;
+        }
+    }
+    /// Updates the falloff, only triggering change detection if it actually changed
+    pub(crate) fn sync_falloff(mut this: Mut<Self>, falloff: LightFalloff) {
+        if this.falloff != falloff {
+            this.falloff = falloff;
+        }
+    }
+}
+/// Responsible for releasing the light claim
+fn on_remove_light_source(
+    mut world: bevy::ecs::world::DeferredWorld,
+    eid: Entity,
+    _: bevy::ecs::component::ComponentId,
+) {
+    if let Some(claim) = world.get::<LightSource>(eid).unwrap().claim.clone() {
+        claim.free(&mut world);
+    }
+}
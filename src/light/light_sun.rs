@@ -0,0 +1,373 @@
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        view::{NoFrustumCulling, RenderLayers},
+    },
+    utils::{HashMap, HashSet},
+};
+use bevy_2delight_physics::prelude::Pos;
+
+use crate::{
+    camera::{camera_shake::CameraShake, DynamicCamera},
+    consts::{ZIX_MAX, ZIX_MIN},
+    layer::{
+        layer_defns::{LightLayer, PRE_LIGHT_RENDER_ORDER},
+        Layer,
+    },
+    plugin::LayersRes,
+    utils::blank_image,
+    LayersCameraSet, LightInteractionSet,
+};
+
+use super::{
+    light_alloc::{LightAllocFailed, LightAllocer},
+    light_broadphase::{BlockerGrid, NearbyCaster},
+    light_interaction::{ShadowGeometry, SHADOW_MAT_HAND},
+    light_mat::LightCutoutMat,
+    light_probe::LightFilter,
+};
+
+/// A light with no position that shines across the whole visible area from one direction, like the sun.
+/// Blockers cast parallel shadows, and the light adds into the `LightLayer` like any other light.
+/// Put it on any entity, and change `angle`/`color` whenever to animate it (sunsets, lightning, etc.).
+/// NOTE: Suns are too big for the light atlas, so each one gets a render layer (and target) of its own.
+///       How many there can be is set by `LightBudget::sun_capacity`.
+///       `LightProbe` and `LightSensor` don't know about suns.
+#[derive(Component, Clone)]
+#[component(on_remove = on_remove_sun_light)]
+pub struct SunLight {
+    /// The direction the light travels, in radians counter-clockwise from +x (so `-FRAC_PI_2` shines straight down)
+    pub angle: f32,
+    /// The color (and brightness) of the light
+    pub color: Color,
+    /// How far (in pixels) light reaches into blockers before the shadow starts, so the faces of walls get lit
+    pub penetration: f32,
+    /// The rendering resources this sun owns, once it's got them
+    claim: Option<SunClaim>,
+}
+impl SunLight {
+    pub fn new(angle: f32, color: Color) -> Self {
+        Self {
+            angle,
+            color,
+            penetration: 0.0,
+            claim: None,
+        }
+    }
+    pub fn with_penetration(mut self, penetration: f32) -> Self {
+        self.penetration = penetration;
+        self
+    }
+}
+/// Responsible for releasing the sun's claim
+fn on_remove_sun_light(
+    mut world: bevy::ecs::world::DeferredWorld,
+    eid: Entity,
+    _: bevy::ecs::component::ComponentId,
+) {
+    if let Some(claim) = world.get::<SunLight>(eid).unwrap().claim.clone() {
+        world
+            .resource_mut::<LightAllocer>()
+            .free_sun_render_layer(claim.rl_usize);
+        for eid in [claim.camera_eid, claim.agg_mesh_eid, claim.shadow_mesh_eid] {
+            if let Some(comms) = world.commands().get_entity(eid) {
+                comms.despawn_recursive();
+            }
+        }
+    }
+}
+
+/// The resources a sun needs to draw: a screen sized target on a render layer of its own, a mesh to copy it
+/// into the `LightLayer`, and a mesh with every shadow
+#[derive(Clone)]
+struct SunClaim {
+    rl_usize: usize,
+    /// The side length of the (square) target
+    size: u32,
+    camera_eid: Entity,
+    agg_mesh_eid: Entity,
+    /// The material on the aggregation mesh, kept around so we can color the sun
+    cutout_mat_hand: Handle<LightCutoutMat>,
+    shadow_mesh_eid: Entity,
+    shadow_mesh_hand: Handle<Mesh>,
+}
+
+/// Everything needed to make sun claims from a system
+#[derive(SystemParam)]
+struct SunClaimParams<'w, 's> {
+    commands: Commands<'w, 's>,
+    res: Res<'w, LayersRes>,
+    allocer: ResMut<'w, LightAllocer>,
+    images: ResMut<'w, Assets<Image>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    cutout_mats: ResMut<'w, Assets<LightCutoutMat>>,
+}
+impl SunClaimParams<'_, '_> {
+    /// Claims a render layer and spawns everything a sun needs. Returns `None` if we're out of sun render layers.
+    fn claim(&mut self) -> Option<SunClaim> {
+        let rl_usize = self.allocer.alloc_sun_render_layer()?;
+        let root_eid = self.res.root_eid();
+        let size = (sun_radius(&self.res).ceil() as u32).max(1) * 2;
+        let image_hand = self.images.add(blank_image(UVec2::splat(size)));
+
+        // Cleared to white, so the shadows have something to cut into
+        let camera_eid = self
+            .commands
+            .spawn((
+                Name::new("SunCamera"),
+                Camera2d,
+                Camera {
+                    order: PRE_LIGHT_RENDER_ORDER as isize,
+                    target: RenderTarget::Image(image_hand.clone()),
+                    clear_color: ClearColorConfig::Custom(Color::WHITE),
+                    ..default()
+                },
+                OrthographicProjection {
+                    near: ZIX_MIN,
+                    far: ZIX_MAX,
+                    scale: 1.0,
+                    ..OrthographicProjection::default_2d()
+                },
+                RenderLayers::layer(rl_usize),
+            ))
+            .set_parent(root_eid)
+            .id();
+
+        // A unit square, scaled up to the target size in `place_sun_lights`
+        let mesh: Mesh2d = self.meshes.add(Rectangle::new(1.0, 1.0)).into();
+        let cutout_mat_hand = self.cutout_mats.add(LightCutoutMat::new(image_hand));
+        let agg_mesh_eid = self
+            .commands
+            .spawn((
+                Name::new("SunAggMesh"),
+                mesh,
+                MeshMaterial2d(cutout_mat_hand.clone()),
+                Transform::default(),
+                Visibility::Inherited,
+                LightLayer::RENDER_LAYERS,
+            ))
+            .set_parent(root_eid)
+            .id();
+
+        let shadow_mesh_hand = self.meshes.add(Mesh::from(Triangle2d::default()));
+        let shadow_mesh_eid = self
+            .commands
+            .spawn((
+                Name::new("SunShadowMesh"),
+                Mesh2d(shadow_mesh_hand.clone()),
+                MeshMaterial2d(SHADOW_MAT_HAND.clone()),
+                Transform::from_translation(Vec3::Z * 100.0),
+                Visibility::Inherited,
+                RenderLayers::layer(rl_usize),
+                NoFrustumCulling,
+            ))
+            .set_parent(root_eid)
+            .id();
+
+        Some(SunClaim {
+            rl_usize,
+            size,
+            camera_eid,
+            agg_mesh_eid,
+            cutout_mat_hand,
+            shadow_mesh_eid,
+            shadow_mesh_hand,
+        })
+    }
+}
+
+/// The radius of a (square) light that covers the visible area plus the light margin
+fn sun_radius(res: &LayersRes) -> f32 {
+    res.screen_size.max_element() as f32 / 2.0 + res.light_margin as f32
+}
+
+/// Gives every new sun a claim. Suns have render layers of their own, so point lights can never starve them.
+/// Suns that don't fit in `LightBudget::sun_capacity` keep trying, but are only warned about once.
+fn claim_sun_lights(
+    mut sun_q: Query<(Entity, &mut SunLight)>,
+    mut claim_params: SunClaimParams,
+    mut failed_writer: EventWriter<LightAllocFailed>,
+    mut starved: Local<HashSet<Entity>>,
+) {
+    for (eid, mut sun) in &mut sun_q {
+        if sun.claim.is_some() {
+            continue;
+        }
+        sun.claim = claim_params.claim();
+        if sun.claim.is_some() {
+            starved.remove(&eid);
+        } else if starved.insert(eid) {
+            warn!("Out of sun render layers, sun {eid} won't be drawn");
+            failed_writer.send(LightAllocFailed { light_eid: eid });
+        }
+    }
+    starved.retain(|eid| sun_q.contains(*eid));
+}
+
+/// What a sun's shadows were last computed with. If none of it changes, neither do the shadows.
+#[derive(Clone, PartialEq)]
+pub(super) struct SunShadowKey {
+    view_center: Vec2,
+    angle: f32,
+    penetration: f32,
+    shadow_mesh_id: AssetId<Mesh>,
+}
+
+/// Casts every blocker's shadow straight along the sun's direction, far enough to cross the whole view
+fn block_sun_lights(
+    sun_q: Query<(Entity, &SunLight)>,
+    dynamic_camera_q: Query<&Pos, With<DynamicCamera>>,
+    grid: Res<BlockerGrid>,
+    res: Res<LayersRes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut cache: Local<HashMap<Entity, (SunShadowKey, u64)>>,
+) {
+    let Ok(leader) = dynamic_camera_q.get_single() else {
+        return;
+    };
+    let view_center = leader.as_vec2().round();
+    let half_view = sun_radius(&res);
+    let reach = half_view * 4.0;
+    for (eid, sun) in &sun_q {
+        let Some(claim) = sun.claim.as_ref() else {
+            continue;
+        };
+        let dir = Vec2::from_angle(sun.angle);
+        // Anything up to `reach` upwind of the view can throw a shadow into it
+        let search_center = view_center - dir * reach / 2.0;
+        let search_extent = half_view + reach / 2.0;
+        let key = SunShadowKey {
+            view_center,
+            angle: sun.angle,
+            penetration: sun.penetration,
+            shadow_mesh_id: claim.shadow_mesh_hand.id(),
+        };
+        if let Some((cached_key, tick)) = cache.get(&eid) {
+            if *cached_key == key && grid.last_changed_near(search_center, search_extent) <= *tick {
+                continue;
+            }
+        }
+
+        let mut lines = grid
+            .static_lines_near(search_center, search_extent)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut filters = vec![];
+        for NearbyCaster {
+            shape,
+            transmittance,
+            ..
+        } in grid.caster_shapes_near(search_center, search_extent)
+        {
+            let Some(transmittance) = transmittance else {
+                lines.extend(shape.to_solid_lines());
+                continue;
+            };
+            // Light passes through translucent casters once, so filter the band behind their widest points
+            let across = |point: &Vec2| dir.perp_dot(*point);
+            let corners = shape
+                .to_solid_lines()
+                .into_iter()
+                .flat_map(|line| [line.a, line.b])
+                .collect::<Vec<_>>();
+            let (Some(low), Some(high)) = (
+                corners
+                    .iter()
+                    .copied()
+                    .min_by(|a, b| across(a).total_cmp(&across(b))),
+                corners
+                    .iter()
+                    .copied()
+                    .max_by(|a, b| across(a).total_cmp(&across(b))),
+            ) else {
+                continue;
+            };
+            filters.push(LightFilter {
+                corners: [low, high, high + dir * reach, low + dir * reach],
+                transmittance,
+            });
+        }
+
+        // Sweeping every edge along the light covers everything behind (and inside) each blocker
+        let mut geometry = ShadowGeometry::default();
+        let start = dir * sun.penetration;
+        for line in lines.iter() {
+            if (line.b - line.a).perp_dot(dir).abs() < 0.01 {
+                // Parallel to the light, so it has no shadow of its own
+                continue;
+            }
+            let (a, b) = (line.a + start, line.b + start);
+            let (far_a, far_b) = (line.a + dir * reach, line.b + dir * reach);
+            geometry.add_tri([(a, 1.0), (b, 1.0), (far_b, 1.0)]);
+            geometry.add_tri([(far_b, 1.0), (far_a, 1.0), (a, 1.0)]);
+        }
+        for filter in filters.iter() {
+            geometry.add_filter(filter);
+        }
+        if let Some(mesh) = meshes.get_mut(key.shadow_mesh_id) {
+            *mesh = geometry.into_mesh();
+        }
+        cache.insert(eid, (key, grid.get_tick()));
+    }
+    cache.retain(|eid, _| sun_q.contains(*eid));
+}
+
+fn update_sun_tints(
+    sun_q: Query<&SunLight, Changed<SunLight>>,
+    mut mats: ResMut<Assets<LightCutoutMat>>,
+) {
+    for sun in &sun_q {
+        let Some(claim) = sun.claim.as_ref() else {
+            continue;
+        };
+        if let Some(mat) = mats.get_mut(claim.cutout_mat_hand.id()) {
+            mat.set_tint(sun.color);
+        }
+    }
+}
+
+/// Keeps each sun's camera on the view, and its aggregation mesh covering the screen
+fn place_sun_lights(
+    sun_q: Query<&SunLight>,
+    dynamic_camera_q: Query<&Pos, With<DynamicCamera>>,
+    camera_shake: Res<CameraShake>,
+    mut tran_q: Query<&mut Transform>,
+) {
+    let Ok(leader) = dynamic_camera_q.get_single() else {
+        return;
+    };
+    let view_center = (leader.as_vec2() + camera_shake.get_offset()).round();
+    for sun in &sun_q {
+        let Some(claim) = sun.claim.as_ref() else {
+            continue;
+        };
+        if let Ok(mut tran) = tran_q.get_mut(claim.camera_eid) {
+            tran.translation.x = view_center.x;
+            tran.translation.y = view_center.y;
+        }
+        if let Ok(mut tran) = tran_q.get_mut(claim.agg_mesh_eid) {
+            tran.translation.x = 0.0;
+            tran.translation.y = 0.0;
+            tran.scale = Vec2::splat(claim.size as f32).extend(1.0);
+        }
+    }
+}
+
+pub(super) fn register_light_sun(app: &mut App) {
+    app.add_systems(Update, claim_sun_lights.in_set(LightInteractionSet));
+    app.add_systems(
+        Update,
+        block_sun_lights
+            .in_set(LightInteractionSet)
+            .after(claim_sun_lights),
+    );
+    app.add_systems(
+        Update,
+        place_sun_lights
+            .in_set(LayersCameraSet)
+            .after(LightInteractionSet),
+    );
+    app.add_systems(PostUpdate, update_sun_tints);
+}
@@ -0,0 +1,259 @@
+use bevy::prelude::*;
+
+/// How far (in radians) to either side of each corner we cast extra rays, so we can see past corners
+const CORNER_EPSILON: f32 = 0.0001;
+
+/// A line segment that blocks light
+#[derive(Clone, Copy, Debug)]
+pub(super) struct SolidLine {
+    pub(super) a: Vec2,
+    pub(super) b: Vec2,
+}
+
+/// Where (as a multiple of `dir`) does a ray starting at `origin` hit the given line, if at all?
+fn ray_hit(origin: Vec2, dir: Vec2, line: &SolidLine) -> Option<f32> {
+    let seg = line.b - line.a;
+    let denom = dir.perp_dot(seg);
+    if denom.abs() < f32::EPSILON {
+        return None;
+    }
+    let diff = line.a - origin;
+    let t = diff.perp_dot(seg) / denom;
+    let u = diff.perp_dot(dir) / denom;
+    (t >= 0.0 && (0.0..=1.0).contains(&u)).then_some(t)
+}
+
+/// Where two lines cross, if they do
+fn line_crossing(first: &SolidLine, second: &SolidLine) -> Option<Vec2> {
+    let seg = first.b - first.a;
+    ray_hit(first.a, seg, second)
+        .filter(|t| *t <= 1.0)
+        .map(|t| first.a + seg * t)
+}
+
+/// A single ray cast out from the light
+#[derive(Clone, Copy, Debug)]
+pub(super) struct VisibilityRay {
+    /// Where the ray first hits something (possibly the bounds)
+    pub(super) visible: Vec2,
+    /// Where the ray would hit the bounds if nothing was in the way
+    pub(super) bound: Vec2,
+}
+
+/// The region a light can actually reach, found by casting rays at every blocker corner.
+/// The light is bounded by a square of half size `extent` around the source.
+pub(super) struct VisibilityPolygon {
+    pub(super) source: Vec2,
+    pub(super) extent: f32,
+    /// Sorted by angle. Consecutive rays (wrapping around) bound a wedge where everything between
+    /// `visible` and `bound` is in shadow
+    pub(super) rays: Vec<VisibilityRay>,
+}
+impl VisibilityPolygon {
+    pub(super) fn new(source: Vec2, extent: f32, lines: &[SolidLine]) -> Self {
+        let corner = |x: f32, y: f32| source + Vec2::new(x, y) * extent;
+        let bounds = [
+            SolidLine {
+                a: corner(-1.0, -1.0),
+                b: corner(-1.0, 1.0),
+            },
+            SolidLine {
+                a: corner(-1.0, 1.0),
+                b: corner(1.0, 1.0),
+            },
+            SolidLine {
+                a: corner(1.0, 1.0),
+                b: corner(1.0, -1.0),
+            },
+            SolidLine {
+                a: corner(1.0, -1.0),
+                b: corner(-1.0, -1.0),
+            },
+        ];
+
+        // Every corner gets a ray, plus one just to either side for blocker corners.
+        // Overlapping blockers make corners where their lines cross, so those count too.
+        let mut corners = lines
+            .iter()
+            .flat_map(|line| [line.a, line.b])
+            .collect::<Vec<_>>();
+        for (ix, first) in lines.iter().enumerate() {
+            corners.extend(
+                lines[(ix + 1)..]
+                    .iter()
+                    .filter_map(|second| line_crossing(first, second))
+                    .filter(|p| {
+                        let offset = *p - source;
+                        offset.x.abs() <= extent && offset.y.abs() <= extent
+                    }),
+            );
+        }
+        let mut angles = Vec::with_capacity(4 + corners.len() * 3);
+        for bound in &bounds {
+            angles.push((bound.a - source).to_angle());
+        }
+        for p in corners {
+            let angle = (p - source).to_angle();
+            angles.extend([angle - CORNER_EPSILON, angle, angle + CORNER_EPSILON]);
+        }
+        for angle in angles.iter_mut() {
+            // Keep everything in (-PI, PI] so sorting gives a proper sweep
+            if *angle <= -std::f32::consts::PI {
+                *angle += std::f32::consts::TAU;
+            } else if *angle > std::f32::consts::PI {
+                *angle -= std::f32::consts::TAU;
+            }
+        }
+        angles.sort_by(|a, b| a.total_cmp(b));
+        angles.dedup();
+
+        let nearest = |dir: Vec2, lines: &[SolidLine]| -> Option<f32> {
+            lines
+                .iter()
+                .filter_map(|line| ray_hit(source, dir, line))
+                .min_by(|a, b| a.total_cmp(b))
+        };
+        let rays = angles
+            .into_iter()
+            .map(|angle| {
+                let dir = Vec2::from_angle(angle);
+                let bound_t = nearest(dir, &bounds).unwrap_or(extent);
+                let visible_t = nearest(dir, lines).map_or(bound_t, |t| t.min(bound_t));
+                VisibilityRay {
+                    visible: source + dir * visible_t,
+                    bound: source + dir * bound_t,
+                }
+            })
+            .collect();
+
+        Self {
+            source,
+            extent,
+            rays,
+        }
+    }
+
+    /// Iterates over each pair of consecutive rays, wrapping around
+    pub(super) fn wedges(&self) -> impl Iterator<Item = (&VisibilityRay, &VisibilityRay)> {
+        self.rays
+            .iter()
+            .zip(self.rays.iter().cycle().skip(1))
+            .take(self.rays.len())
+    }
+
+    /// Lets light continue `depth` past wherever it first hits a blocker (but never past the bounds)
+    pub(super) fn penetrate(&mut self, depth: f32) {
+        for ray in self.rays.iter_mut() {
+            let blocked = ray.visible.distance(ray.bound);
+            if blocked > 0.0 {
+                let dir = (ray.bound - ray.visible) / blocked;
+                ray.visible += dir * depth.min(blocked);
+            }
+        }
+    }
+
+    /// Blocks the light completely, as if something was sitting right on top of the source
+    pub(super) fn occlude(&mut self) {
+        for ray in self.rays.iter_mut() {
+            ray.visible = self.source;
+        }
+    }
+
+    /// Can light from the source reach this point unblocked?
+    pub(super) fn contains(&self, point: Vec2) -> bool {
+        let offset = point - self.source;
+        if offset.x.abs() > self.extent || offset.y.abs() > self.extent {
+            return false;
+        }
+        self.wedges().any(|(left, right)| {
+            // Inside the triangle (source, left.visible, right.visible), which winds counter-clockwise
+            let left = left.visible - self.source;
+            let right = right.visible - self.source;
+            left.perp_dot(right) > 0.0
+                && left.perp_dot(offset) >= 0.0
+                && offset.perp_dot(right) >= 0.0
+                && (right - left).perp_dot(offset - left) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unblocked_light_fills_its_extent() {
+        let visibility = VisibilityPolygon::new(Vec2::ZERO, 10.0, &[]);
+        assert_eq!(visibility.rays.len(), 4);
+        for ray in visibility.rays.iter() {
+            assert!(ray.visible.distance(ray.bound) < 0.01);
+            assert!((ray.bound.abs() - Vec2::splat(10.0)).length() < 0.01);
+        }
+    }
+
+    #[test]
+    fn walls_shadow_what_is_behind_them() {
+        let wall = SolidLine {
+            a: Vec2::new(4.0, -2.0),
+            b: Vec2::new(4.0, 2.0),
+        };
+        let visibility = VisibilityPolygon::new(Vec2::ZERO, 10.0, &[wall]);
+        let wall_angle = Vec2::new(4.0, 2.0).to_angle();
+        let mut blocked = 0;
+        for ray in visibility.rays.iter() {
+            let angle = ray.bound.to_angle().abs();
+            if angle < wall_angle - CORNER_EPSILON / 2.0 {
+                // Stopped by the wall
+                assert!((ray.visible.x - 4.0).abs() < 0.01);
+                assert!((ray.bound.x - 10.0).abs() < 0.01);
+                blocked += 1;
+            } else if angle > wall_angle + CORNER_EPSILON / 2.0 {
+                // Past the end of the wall the light gets through
+                assert!(ray.visible.distance(ray.bound) < 0.01);
+            }
+        }
+        assert!(blocked > 0);
+    }
+
+    #[test]
+    fn penetration_lights_the_face_of_walls() {
+        let wall = SolidLine {
+            a: Vec2::new(4.0, -2.0),
+            b: Vec2::new(4.0, 2.0),
+        };
+        let mut visibility = VisibilityPolygon::new(Vec2::ZERO, 10.0, &[wall]);
+        assert!(!visibility.contains(Vec2::new(5.0, 0.0)));
+        visibility.penetrate(2.0);
+        assert!(visibility.contains(Vec2::new(5.0, 0.0)));
+        assert!(!visibility.contains(Vec2::new(7.0, 0.0)));
+    }
+
+    /// The four sides of the box from `min` to `max`
+    fn box_lines(min: Vec2, max: Vec2) -> [SolidLine; 4] {
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+        std::array::from_fn(|ix| SolidLine {
+            a: corners[ix],
+            b: corners[(ix + 1) % 4],
+        })
+    }
+
+    #[test]
+    fn overlapping_blockers_light_their_inner_corner() {
+        // A horizontal bar poking into a vertical one. Seen from above, the top of the horizontal bar
+        // meets the side of the vertical one at (4, 1), which isn't a corner of either box.
+        let mut lines = box_lines(Vec2::new(4.0, -3.0), Vec2::new(6.0, 3.0)).to_vec();
+        lines.extend(box_lines(Vec2::new(2.0, -1.0), Vec2::new(8.0, 1.0)));
+        let visibility = VisibilityPolygon::new(Vec2::new(0.0, 6.0), 10.0, &lines);
+        assert!(visibility.contains(Vec2::new(3.8, 1.2)));
+        assert!(visibility.contains(Vec2::new(3.0, 1.1)));
+        assert!(!visibility.contains(Vec2::new(3.0, 0.5)));
+        assert!(!visibility.contains(Vec2::new(5.0, 1.5)));
+    }
+
+    #[test]
+    fn occluded_lights_reach_nothing() {
+        let mut visibility = VisibilityPolygon::new(Vec2::ZERO, 10.0, &[]);
+        visibility.occlude();
+        assert!(!visibility.contains(Vec2::new(3.0, 3.0)));
+    }
+}
@@ -1,28 +1,56 @@
 use bevy::{asset::embedded_asset, prelude::*, sprite::Material2dPlugin};
-use light_alloc::LightAllocer;
-use light_interaction::BLACK_MAT_HAND;
-use light_mat::{LightApplyMat, LightCutoutMat};
+use light_alloc::LightBudget;
+use light_interaction::SHADOW_MAT_HAND;
+use light_mat::{LightApplyMat, LightCutoutMat, ProcLightMat, ShadowMat};
 
-mod light_alloc;
+pub(crate) mod light_alloc;
+pub(crate) mod light_alpha_caster;
+pub(crate) mod light_ambience;
+mod light_broadphase;
+pub(crate) mod light_caster;
+mod light_contour;
+mod light_cull;
 mod light_interaction;
 pub(crate) mod light_man;
 pub(crate) mod light_mat;
+pub(crate) mod light_probe;
+pub(crate) mod light_proc;
+pub(crate) mod light_sensor;
+mod light_source;
+pub(crate) mod light_sun;
+mod light_visibility;
 
-fn setup_black_mat(mut mats: ResMut<Assets<ColorMaterial>>) {
-    mats.insert(BLACK_MAT_HAND.id(), Color::BLACK.into());
+fn setup_shadow_mat(mut mats: ResMut<Assets<ShadowMat>>) {
+    mats.insert(SHADOW_MAT_HAND.id(), ShadowMat::default());
 }
 
-pub(crate) struct LayersLightPlugin;
+pub(crate) struct LayersLightPlugin {
+    pub(crate) budget: LightBudget,
+}
 impl Plugin for LayersLightPlugin {
     fn build(&self, app: &mut App) {
         embedded_asset!(app, "light_apply_mat.wgsl");
         embedded_asset!(app, "light_cutout_mat.wgsl");
+        embedded_asset!(app, "light_proc_mat.wgsl");
+        embedded_asset!(app, "light_shadow_mat.wgsl");
 
         app.add_plugins(Material2dPlugin::<LightApplyMat>::default());
         app.add_plugins(Material2dPlugin::<LightCutoutMat>::default());
+        app.add_plugins(Material2dPlugin::<ProcLightMat>::default());
+        app.add_plugins(Material2dPlugin::<ShadowMat>::default());
 
-        app.insert_resource(LightAllocer::default());
+        light_alloc::register_light_alloc(app, self.budget);
+        light_alpha_caster::register_light_alpha_caster(app);
+        light_ambience::register_light_ambience(app);
+        light_broadphase::register_light_broadphase(app);
+        light_caster::register_light_caster(app);
+        light_cull::register_light_cull(app);
+        light_interaction::register_light_interaction(app);
+        light_probe::register_light_probe(app);
+        light_proc::register_light_proc(app);
+        light_sensor::register_light_sensor(app);
+        light_sun::register_light_sun(app);
 
-        app.add_systems(Startup, setup_black_mat);
+        app.add_systems(Startup, setup_shadow_mat);
     }
 }
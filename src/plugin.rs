@@ -2,8 +2,14 @@ use bevy::{asset::AssetMetaCheck, prelude::*};
 
 use crate::{
     camera::{setup_smush_camera, LayersCameraPlugin},
-    layer::setup_all_layers,
-    light::LayersLightPlugin,
+    layer::{
+        layer_defns::{
+            BgLayer, FgLayer, LightLayer, MainAmbienceLayer, MainDetailLayer, MainStaticLayer,
+            MenuLayer, OverlayLayer, SmushLayer, TransitionLayer,
+        },
+        LayerDefnPlugin, RegisteredLayers,
+    },
+    light::{light_alloc::LightBudget, LayersLightPlugin},
     parallax::LayersParallaxPlugin,
 };
 
@@ -13,6 +19,8 @@ pub(crate) struct LayersRes {
     pub screen_size: UVec2,
     /// How many multiples of the screen size are things like menu, overlay, transition...
     pub overlay_growth: u32,
+    /// How far past the edge of the screen lighting is drawn
+    pub light_margin: u32,
     /// Root component
     _root_eid: Entity,
 }
@@ -20,6 +28,10 @@ impl LayersRes {
     pub(crate) fn root_eid(&self) -> Entity {
         self._root_eid
     }
+    /// The size of the `LightLayer` target, which is the screen grown by the light margin on every side
+    pub(crate) fn light_target_size(&self) -> UVec2 {
+        self.screen_size + UVec2::splat(self.light_margin * 2)
+    }
 }
 
 pub(crate) fn init_root_eid(mut commands: Commands, mut layers_res: ResMut<LayersRes>) {
@@ -39,6 +51,12 @@ pub struct LayersPlugin {
     /// How many multiples of the screen size are things like menu, overlay, transition...
     pub overlay_growth: u32,
     pub window: Window,
+    /// How many lights can be drawn at once, and which render layers they use
+    pub light_budget: LightBudget,
+    /// How far (in pixels) past the edge of the screen lighting is drawn and lights are kept active.
+    /// A bigger margin means camera shake and fast pans don't reveal unlit edges, and fewer lights
+    /// pop in as the camera moves, at the cost of a bigger light target and more light work.
+    pub light_margin: u32,
 }
 impl Plugin for LayersPlugin {
     fn build(&self, app: &mut App) {
@@ -54,19 +72,41 @@ impl Plugin for LayersPlugin {
                 })
                 .set(ImagePlugin::default_nearest()),
         );
-        app.add_plugins(LayersLightPlugin);
+        app.add_plugins(LayersLightPlugin {
+            budget: self.light_budget,
+        });
         app.add_plugins(LayersCameraPlugin);
         app.add_plugins(LayersParallaxPlugin);
+        app.add_plugins((
+            LayerDefnPlugin::<LightLayer>::default(),
+            LayerDefnPlugin::<BgLayer>::default(),
+            LayerDefnPlugin::<MainAmbienceLayer>::default(),
+            LayerDefnPlugin::<MainDetailLayer>::default(),
+            LayerDefnPlugin::<MainStaticLayer>::default(),
+            LayerDefnPlugin::<FgLayer>::default(),
+            LayerDefnPlugin::<OverlayLayer>::default(),
+            LayerDefnPlugin::<MenuLayer>::default(),
+            LayerDefnPlugin::<TransitionLayer>::default(),
+            LayerDefnPlugin::<SmushLayer>::default(),
+        ));
 
         app.insert_resource(LayersRes {
             screen_size: self.screen_size,
             overlay_growth: self.overlay_growth,
+            light_margin: self.light_margin,
             _root_eid: Entity::PLACEHOLDER,
         });
 
-        app.add_systems(
-            Startup,
-            (init_root_eid, setup_all_layers, setup_smush_camera).chain(),
-        );
+        app.add_systems(Startup, (init_root_eid, setup_smush_camera).chain());
+    }
+
+    fn finish(&self, app: &mut App) {
+        // Custom layers may have been added after this plugin, so they can only be checked now
+        if let Some(registered) = app.world().get_resource::<RegisteredLayers>() {
+            registered.check_reserved(
+                self.light_budget.render_layers(),
+                "lights (see `LightBudget`)",
+            );
+        }
     }
 }
@@ -9,9 +9,13 @@ use crate::plugin::LayersRes;
 
 pub(crate) fn blank_screen_image(res: &LayersRes, is_overlay: bool) -> Image {
     let mult = if is_overlay { res.overlay_growth } else { 1 };
+    blank_image(res.screen_size * mult)
+}
+
+pub(crate) fn blank_image(size: UVec2) -> Image {
     let target_extent = Extent3d {
-        width: res.screen_size.x * mult,
-        height: res.screen_size.y * mult,
+        width: size.x,
+        height: size.y,
         ..default()
     };
     // Makes the image